@@ -36,6 +36,13 @@ async fn main() {
     ctx.add_function("log", log);
     ctx.add_function("passthrough", passthrough);
 
+    let mut calls = 0;
+    ctx.add_function_mut("count", move |ctx, _this, _args| {
+        calls += 1;
+        println!("count() has been called {} times", calls);
+        Ok(ctx.undefined())
+    });
+
     let script = &js::String::new("log(\"it works\", 42 * 124123.21)").unwrap();
     match ctx.evaluate_script(script).await {
         Ok(v) => println!("Success!"),
@@ -47,4 +54,9 @@ async fn main() {
         Ok(v) => println!("Result: {}, {:?}", v.to_string(), &v),
         Err(e) => eprintln!("{:?}", &e),
     };
+
+    let script = &js::String::new("count(); count()").unwrap();
+    if let Err(e) = ctx.evaluate_script(script).await {
+        eprintln!("{:?}", &e);
+    }
 }
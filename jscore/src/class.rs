@@ -0,0 +1,1103 @@
+use crate::types::{
+    function_owner, rust_function_defn, Class, Context, ContextType, Exception, Object, String,
+    Value, ValueType,
+};
+use javascriptcore_sys::*;
+use lazy_static::lazy_static;
+use std::any::{Any, TypeId};
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fmt;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr::{null, null_mut};
+use std::sync::{Arc, Mutex};
+
+lazy_static! {
+    static ref REGISTRIES: Mutex<HashMap<usize, ClassRegistry>> = Mutex::new(HashMap::new());
+    static ref GARBAGE: Mutex<HashMap<usize, Vec<Garbage>>> = Mutex::new(HashMap::new());
+}
+
+/// Rust data released by a finalizer, to be dropped outside of garbage
+/// collection. Finalizers must not call into JavaScriptCore, which dropping
+/// a `Rooted` or running a class's finalize hook may well do.
+/// Finalizers may run on any thread, and so may whichever thread collects the
+/// garbage, so everything queued must be `Send`.
+pub(crate) struct Garbage(Box<dyn FnOnce() + Send>);
+
+impl Garbage {
+    pub(crate) fn new<F: FnOnce() + Send + 'static>(drop: F) -> Garbage {
+        Garbage(Box::new(drop))
+    }
+}
+
+pub(crate) fn defer(group: usize, garbage: Garbage) {
+    if let Ok(mut queue) = GARBAGE.lock() {
+        queue.entry(group).or_insert_with(Vec::new).push(garbage);
+    }
+}
+
+/// Drops the garbage finalizers have queued for `group`.
+pub(crate) fn collect_garbage(group: JSContextGroupRef) {
+    let garbage = match GARBAGE.lock() {
+        Ok(mut queue) => queue.remove(&(group as usize)),
+        Err(_) => None,
+    };
+    for Garbage(drop) in garbage.into_iter().flatten() {
+        // One panicking destructor shouldn't leak the rest.
+        let _ = panic::catch_unwind(AssertUnwindSafe(drop));
+    }
+}
+
+/// Runs a Rust callback on behalf of JavaScript, turning a panic into an
+/// error rather than unwinding through JavaScriptCore's frames.
+pub(crate) fn guard<R, F>(callback: F) -> Result<R, String>
+where
+    F: FnOnce() -> Result<R, String>,
+{
+    panic::catch_unwind(AssertUnwindSafe(callback))
+        .unwrap_or_else(|_| Err(String::from("native callback panicked")))
+}
+
+/// Native classes shared by every context in a context group.
+#[derive(Default)]
+struct ClassRegistry {
+    handles: usize,
+    function: Option<Class>,
+    classes: HashMap<TypeId, NativeClass>,
+    intrinsics: HashMap<usize, Intrinsics>,
+}
+
+// The built-in functions the crate calls on the embedder's behalf, captured
+// when a global context is created so that scripts replacing, say,
+// `Object.defineProperty` can't change what the crate does.
+const INTRINSIC_FUNCTIONS: &[&str] = &[
+    "Date.prototype.toISOString",
+    "Error",
+    "Map",
+    "Map.prototype.forEach",
+    "Object.defineProperty",
+    "Object.getOwnPropertyDescriptor",
+    "Object.getPrototypeOf",
+    "Object.keys",
+    "Object.setPrototypeOf",
+    "Reflect.ownKeys",
+    "Set",
+    "Set.prototype.forEach",
+];
+
+/// The captured functions of one global context, protected from garbage
+/// collection until its last `GlobalContext` handle drops.
+struct Intrinsics {
+    handles: usize,
+    functions: HashMap<&'static str, Value>,
+}
+
+// Looks up a path such as `Object.defineProperty` from the global object.
+unsafe fn lookup(ctx: Context, path: &str) -> JSValueRef {
+    let mut value = JSContextGetGlobalObject(ctx.0) as JSValueRef;
+    for name in path.split('.') {
+        if value.is_null() || !JSValueIsObject(ctx.0, value) {
+            return null();
+        }
+        let name = String::from(name);
+        let mut exception = null();
+        value = JSObjectGetProperty(ctx.0, value as JSObjectRef, *name, &mut exception);
+        if !exception.is_null() {
+            return null();
+        }
+    }
+    value
+}
+
+pub(crate) fn retain_intrinsics(ctx: JSGlobalContextRef) {
+    let key = ctx as usize;
+    let ctx = Context(ctx);
+    let retained = with_registry(ctx, |registry| match registry.intrinsics.get_mut(&key) {
+        Some(intrinsics) => {
+            intrinsics.handles += 1;
+            true
+        }
+        None => false,
+    });
+    if retained {
+        return;
+    }
+
+    // Looking the functions up may run getters, so not under the lock.
+    let functions = INTRINSIC_FUNCTIONS
+        .iter()
+        .filter_map(|&path| {
+            let function = unsafe { lookup(ctx, path) };
+            if function.is_null() {
+                return None;
+            }
+            unsafe { JSValueProtect(ctx.0, function) };
+            Some((path, Value::from(ctx, function)))
+        })
+        .collect::<HashMap<_, _>>();
+    let duplicate = with_registry(ctx, |registry| {
+        let intrinsics = registry
+            .intrinsics
+            .entry(key)
+            .or_insert_with(|| Intrinsics {
+                handles: 0,
+                functions: HashMap::new(),
+            });
+        intrinsics.handles += 1;
+        if intrinsics.functions.is_empty() {
+            intrinsics.functions = functions;
+            None
+        } else {
+            Some(functions)
+        }
+    });
+    // Another thread captured them first.
+    for function in duplicate.into_iter().flat_map(HashMap::into_iter) {
+        unsafe { JSValueUnprotect(ctx.0, (function.1).0) };
+    }
+}
+
+pub(crate) fn release_intrinsics(ctx: JSGlobalContextRef) {
+    let key = ctx as usize;
+    let ctx = Context(ctx);
+    let released = with_registry(ctx, |registry| {
+        let intrinsics = registry.intrinsics.get_mut(&key)?;
+        intrinsics.handles -= 1;
+        if intrinsics.handles > 0 {
+            return None;
+        }
+        registry.intrinsics.remove(&key)
+    });
+    for function in released.into_iter().flat_map(|i| i.functions.into_iter()) {
+        unsafe { JSValueUnprotect(ctx.0, (function.1).0) };
+    }
+}
+
+/// A captured built-in such as `Object.defineProperty`. Contexts without a
+/// `GlobalContext` handle have nothing captured, so get the current one.
+pub(crate) fn intrinsic(ctx: Context, path: &str) -> JSValueRef {
+    let key = unsafe { JSContextGetGlobalContext(ctx.0) } as usize;
+    let captured = with_registry(ctx, |registry| {
+        let intrinsics = registry.intrinsics.get(&key)?;
+        intrinsics.functions.get(path).map(|function| function.0)
+    });
+    captured.unwrap_or_else(|| unsafe { lookup(ctx, path) })
+}
+
+struct NativeClass {
+    class: Class,
+    spec: Arc<dyn Any + Send + Sync>,
+    upcast: Option<Arc<dyn Upcast>>,
+    parent: Option<TypeId>,
+    install_methods: unsafe fn(Context, JSObjectRef),
+}
+
+pub(crate) fn retain_registry(group: JSContextGroupRef) {
+    let mut registries = REGISTRIES.lock().unwrap();
+    let registry = registries
+        .entry(group as usize)
+        .or_insert_with(ClassRegistry::default);
+    registry.handles += 1;
+}
+
+pub(crate) fn release_registry(group: JSContextGroupRef) {
+    let registry = {
+        let mut registries = REGISTRIES.lock().unwrap();
+        let key = group as usize;
+        let handles = match registries.get_mut(&key) {
+            Some(registry) => {
+                registry.handles -= 1;
+                registry.handles
+            }
+            None => return,
+        };
+        if handles > 0 {
+            return;
+        }
+        registries.remove(&key)
+    };
+
+    // Release the classes outside of the lock.
+    drop(registry);
+}
+
+fn with_registry<R, F>(ctx: Context, f: F) -> R
+where
+    F: FnOnce(&mut ClassRegistry) -> R,
+{
+    let group = unsafe { JSContextGetGroup(ctx.0) };
+    collect_garbage(group);
+    let group = group as usize;
+    let mut registries = REGISTRIES.lock().unwrap();
+    match registries.get_mut(&group) {
+        Some(registry) => f(registry),
+        // Nothing would ever release a cached class for a group we hold no
+        // handle on, so hand out uncached classes instead.
+        None => f(&mut ClassRegistry::default()),
+    }
+}
+
+pub(crate) fn function_class(ctx: Context) -> Class {
+    with_registry(ctx, |registry| {
+        registry
+            .function
+            .get_or_insert_with(|| Class::new(&rust_function_defn()))
+            .clone()
+    })
+}
+
+fn native_class<T: 'static>(ctx: Context) -> Option<(Class, Arc<ClassSpec<T>>)> {
+    with_registry(ctx, |registry| {
+        let native = registry.classes.get(&TypeId::of::<T>())?;
+        let spec = native.spec.clone().downcast::<ClassSpec<T>>().ok()?;
+        Some((native.class.clone(), spec))
+    })
+}
+
+pub(crate) fn class_name<T: 'static>(ctx: Context) -> Option<std::string::String> {
+    native_class::<T>(ctx).map(|(_, spec)| spec.name.clone())
+}
+
+fn instantiate<T: Send + 'static>(
+    ctx: Context,
+    class: &Class,
+    spec: Arc<ClassSpec<T>>,
+    value: T,
+) -> JSObjectRef {
+    let data = Box::new(NativeData {
+        group: unsafe { JSContextGetGroup(ctx.0) } as usize,
+        value: RefCell::new(Box::new(value)),
+        upcast: spec.upcast.clone(),
+        spec,
+    });
+    unsafe { JSObjectMake(ctx.0, class.0, Box::into_raw(data) as _) }
+}
+
+/// Finds the private data of `object` if it is an instance of `T`'s class.
+pub(crate) fn instance_data<T: 'static>(object: &Object) -> Option<&NativeData> {
+    let ctx = object.0;
+    let (class, _) = native_class::<T>(ctx)?;
+    unsafe { NativeData::from_object(ctx, &class, object.1) }
+}
+
+pub(crate) fn make_instance<T: Send + 'static>(
+    ctx: Context,
+    value: T,
+) -> Result<Object, ClassError> {
+    let (class, spec) = native_class::<T>(ctx).ok_or(ClassError::NotRegistered)?;
+    let ptr = instantiate(ctx, &class, spec, value);
+    unsafe { prepare_prototype::<T>(ctx, JSObjectGetPrototype(ctx.0, ptr)) };
+    Ok(Object(ctx, ptr))
+}
+
+// JavaScriptCore creates a prototype for each class in each global context.
+// Scripts can only reach it through an instance or constructor, so methods
+// are installed on it, and on its native ancestors, before handing one out.
+unsafe fn prepare_prototype<T: 'static>(ctx: Context, prototype: JSValueRef) {
+    let mut prototype = prototype;
+    let mut type_id = Some(TypeId::of::<T>());
+    while let Some(id) = type_id {
+        if prototype.is_null() || !JSValueIsObject(ctx.0, prototype) {
+            return;
+        }
+        let prototype_object = prototype as JSObjectRef;
+        let native = with_registry(ctx, |registry| {
+            let native = registry.classes.get(&id)?;
+            Some((native.install_methods, native.parent))
+        });
+        let (install_methods, parent) = match native {
+            Some(native) => native,
+            None => return,
+        };
+        install_methods(ctx, prototype_object);
+        type_id = parent;
+        prototype = JSObjectGetPrototype(ctx.0, prototype_object);
+    }
+}
+
+// Each method is a host function that owns its closure, so calling it never
+// depends on anything a script could change, such as the function's `name`.
+unsafe fn install_methods<T: 'static>(ctx: Context, prototype: JSObjectRef) {
+    let spec = match native_class::<T>(ctx) {
+        Some((_, spec)) => spec,
+        None => return,
+    };
+    let owner = TypeId::of::<T>();
+    let prototype = Object(ctx, prototype);
+
+    // Methods are read-only and can't be deleted, so one that's present
+    // means they all are.
+    if let Some(name) = spec.methods.keys().next() {
+        let key = String::from(name.as_str());
+        let current = JSObjectGetProperty(ctx.0, prototype.1, *key, null_mut());
+        if function_owner(ctx, current) == Some(owner) {
+            return;
+        }
+    }
+
+    let attributes =
+        (kJSPropertyAttributeReadOnly | kJSPropertyAttributeDontDelete) as JSPropertyAttributes;
+    for name in spec.methods.keys() {
+        let key = String::from(name.as_str());
+        let method_spec = spec.clone();
+        let method_name = name.clone();
+        let function = prototype.make_method(&key, owner, move |ctx, this, args| {
+            let class = native_class::<T>(ctx)
+                .map(|(class, _)| class)
+                .ok_or_else(not_registered)?;
+            let method = &method_spec.methods[&method_name];
+            let data = receiver(ctx, &class, &method_spec, this.1)?;
+            let mut this = data
+                .borrow_mut::<T>()
+                .ok_or_else(|| already_borrowed(&method_spec))?;
+            method(ctx, &mut this, args)
+        });
+        JSObjectSetProperty(ctx.0, prototype.1, *key, function.1, attributes, null_mut());
+    }
+}
+
+pub(crate) fn make_constructor<T: Send + 'static>(ctx: Context) -> Result<Object, ClassError> {
+    let (class, spec) = native_class::<T>(ctx).ok_or(ClassError::NotRegistered)?;
+    let callback = if spec.constructor.is_some() {
+        Some(construct::<T>)
+    } else {
+        None
+    };
+    let native = unsafe { JSObjectMakeConstructor(ctx.0, class.0, callback) };
+    unsafe {
+        let key = String::from("prototype");
+        prepare_prototype::<T>(ctx, JSObjectGetProperty(ctx.0, native, *key, null_mut()));
+    }
+    let ptr = unsafe { subclassable_constructor(ctx, native, &spec.name) }
+        .map_err(ClassError::Exception)?;
+    Ok(Object(ctx, ptr))
+}
+
+// Constructors made by `JSObjectMakeConstructor` never see `new.target`, so
+// instances would always get the native prototype, even when constructed
+// through `super()` in a JavaScript subclass. Wrap them in a plain function
+// that moves the instance onto the subclass prototype instead.
+//
+// The built-ins it uses are passed in, as captured when the context was
+// created, so scripts replacing them can't break or hijack subclassing.
+const SUBCLASSABLE_CONSTRUCTOR: &str = r#"
+function constructor(...args) {
+    if (new.target === undefined) {
+        throw new TypeError("Class constructor " + name + " cannot be invoked without 'new'");
+    }
+    const instance = new native(...args);
+    if (new.target !== constructor) {
+        setPrototypeOf(instance, new.target.prototype);
+    }
+    return instance;
+}
+defineProperty(constructor, "name", { __proto__: null, value: name });
+constructor.prototype = native.prototype;
+defineProperty(constructor.prototype, "constructor", {
+    __proto__: null,
+    value: constructor,
+    writable: true,
+    configurable: true,
+});
+return constructor;
+"#;
+
+unsafe fn subclassable_constructor(
+    ctx: Context,
+    native: JSObjectRef,
+    name: &str,
+) -> Result<JSObjectRef, Exception> {
+    let mut args = vec![native as JSValueRef, String::from(name).to_js_value(&ctx)];
+    for intrinsic_name in &["Object.setPrototypeOf", "Object.defineProperty"] {
+        let function = intrinsic(ctx, intrinsic_name);
+        if function.is_null() {
+            let message = format!("{} is not available", intrinsic_name);
+            return Err(Exception::make(ctx, "TypeError", &message));
+        }
+        args.push(function);
+    }
+
+    let params = ["native", "name", "setPrototypeOf", "defineProperty"];
+    let params = params.iter().map(|&p| String::from(p)).collect::<Vec<_>>();
+    let params = params.iter().map(|p| p.0).collect::<Vec<_>>();
+    let body = String::from(SUBCLASSABLE_CONSTRUCTOR);
+
+    let mut exception = null();
+    let factory = JSObjectMakeFunction(
+        ctx.0,
+        null_mut(),
+        params.len() as _,
+        params.as_ptr(),
+        body.0,
+        null_mut(),
+        1,
+        &mut exception,
+    );
+    ctx.check(exception)?;
+
+    let constructor = JSObjectCallAsFunction(
+        ctx.0,
+        factory,
+        null_mut(),
+        args.len(),
+        args.as_ptr(),
+        &mut exception,
+    );
+    ctx.check(exception)?;
+    let constructor = JSValueToObject(ctx.0, constructor, &mut exception);
+    ctx.check(exception)?;
+    Ok(constructor)
+}
+
+#[derive(Debug, Clone)]
+pub enum ClassError {
+    AlreadyRegistered(std::string::String),
+    InvalidName(std::string::String),
+    NotRegistered,
+    ParentNotRegistered,
+    Exception(Exception),
+}
+
+impl std::error::Error for ClassError {}
+impl fmt::Display for ClassError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClassError::AlreadyRegistered(name) => {
+                write!(f, "a class is already registered for `{}`", name)
+            }
+            ClassError::InvalidName(name) => write!(f, "invalid class member name: {:?}", name),
+            ClassError::NotRegistered => write!(f, "no class is registered for this type"),
+            ClassError::ParentNotRegistered => {
+                write!(f, "no class is registered for the parent type")
+            }
+            ClassError::Exception(exception) => write!(f, "{}", exception),
+        }
+    }
+}
+
+// Specs are shared by every thread using a context group, so everything they
+// hold must be `Send` and `Sync`.
+type Getter<T> = Box<dyn Fn(Context, &T) -> Result<Value, String> + Send + Sync>;
+type Setter<T> = Box<dyn Fn(Context, &mut T, Value) -> Result<(), String> + Send + Sync>;
+type Method<T> = Box<dyn Fn(Context, &mut T, Vec<Value>) -> Result<Value, String> + Send + Sync>;
+type Constructor<T> = Box<dyn Fn(Context, Vec<Value>) -> Result<T, String> + Send + Sync>;
+type Initialize<T> = Box<dyn Fn(Context, Object, &mut T) + Send + Sync>;
+type HasInstance = Box<dyn Fn(Context, Object, Value) -> Result<bool, String> + Send + Sync>;
+type ConvertToType<T> =
+    Box<dyn Fn(Context, &T, ValueType) -> Result<Option<Value>, String> + Send + Sync>;
+
+struct StaticValue<T> {
+    get: Getter<T>,
+    set: Option<Setter<T>>,
+}
+
+pub(crate) struct ClassSpec<T> {
+    name: std::string::String,
+    values: HashMap<std::string::String, StaticValue<T>>,
+    methods: HashMap<std::string::String, Method<T>>,
+    constructor: Option<Constructor<T>>,
+    initialize: Option<Initialize<T>>,
+    finalize: Option<Box<dyn Fn(T) + Send + Sync>>,
+    has_instance: Option<HasInstance>,
+    convert_to_type: Option<ConvertToType<T>>,
+    upcast: Option<Arc<dyn Upcast>>,
+}
+
+trait Finalize: Send + Sync {
+    fn finalize(&self, value: Box<dyn Any + Send>);
+}
+
+impl<T: 'static> Finalize for ClassSpec<T> {
+    fn finalize(&self, value: Box<dyn Any + Send>) {
+        if let (Ok(value), Some(finalize)) = (value.downcast::<T>(), &self.finalize) {
+            finalize(*value);
+        }
+    }
+}
+
+/// Finds the value of an ancestor class within the value of a subclass.
+trait Upcast: Send + Sync {
+    fn upcast<'a>(&self, value: &'a dyn Any, target: TypeId) -> Option<&'a dyn Any>;
+    fn upcast_mut<'a>(&self, value: &'a mut dyn Any, target: TypeId) -> Option<&'a mut dyn Any>;
+}
+
+struct Parent<S, P> {
+    grandparent: Option<Arc<dyn Upcast>>,
+    _marker: PhantomData<fn(S) -> P>,
+}
+
+impl<S, P> Upcast for Parent<S, P>
+where
+    S: AsRef<P> + AsMut<P> + 'static,
+    P: 'static,
+{
+    fn upcast<'a>(&self, value: &'a dyn Any, target: TypeId) -> Option<&'a dyn Any> {
+        let parent: &P = value.downcast_ref::<S>()?.as_ref();
+        if TypeId::of::<P>() == target {
+            return Some(parent);
+        }
+        self.grandparent.as_ref()?.upcast(parent, target)
+    }
+
+    fn upcast_mut<'a>(&self, value: &'a mut dyn Any, target: TypeId) -> Option<&'a mut dyn Any> {
+        let parent: &mut P = value.downcast_mut::<S>()?.as_mut();
+        if TypeId::of::<P>() == target {
+            return Some(parent);
+        }
+        self.grandparent.as_ref()?.upcast_mut(parent, target)
+    }
+}
+
+struct ParentLink {
+    type_id: TypeId,
+    link: fn(Option<Arc<dyn Upcast>>) -> Arc<dyn Upcast>,
+}
+
+/// The private data of every instance of a native class.
+pub(crate) struct NativeData {
+    group: usize,
+    value: RefCell<Box<dyn Any + Send>>,
+    spec: Arc<dyn Finalize>,
+    upcast: Option<Arc<dyn Upcast>>,
+}
+
+impl NativeData {
+    unsafe fn from_object<'a>(
+        ctx: Context,
+        class: &Class,
+        object: JSObjectRef,
+    ) -> Option<&'a NativeData> {
+        if object.is_null() || !JSValueIsObjectOfClass(ctx.0, object, class.0) {
+            return None;
+        }
+        let data = JSObjectGetPrivate(object) as *const NativeData;
+        if data.is_null() {
+            None
+        } else {
+            Some(&*data)
+        }
+    }
+
+    // Instances of subclasses hold the value of their most derived class.
+    fn find<'a, T: 'static>(&self, value: &'a dyn Any) -> Option<&'a T> {
+        if value.is::<T>() {
+            return value.downcast_ref::<T>();
+        }
+        let upcast = self.upcast.as_ref()?;
+        upcast.upcast(value, TypeId::of::<T>())?.downcast_ref::<T>()
+    }
+
+    fn find_mut<'a, T: 'static>(&self, value: &'a mut dyn Any) -> Option<&'a mut T> {
+        if value.is::<T>() {
+            return value.downcast_mut::<T>();
+        }
+        let upcast = self.upcast.as_ref()?;
+        upcast
+            .upcast_mut(value, TypeId::of::<T>())?
+            .downcast_mut::<T>()
+    }
+
+    pub(crate) fn borrow<T: 'static>(&self) -> Option<Ref<T>> {
+        let value = self.value.try_borrow().ok()?;
+        self.find::<T>(&**value)?;
+        Some(Ref::map(value, |value| self.find::<T>(&**value).unwrap()))
+    }
+
+    pub(crate) fn borrow_mut<T: 'static>(&self) -> Option<RefMut<T>> {
+        let mut value = self.value.try_borrow_mut().ok()?;
+        self.find_mut::<T>(&mut **value)?;
+        Some(RefMut::map(value, |value| {
+            self.find_mut::<T>(&mut **value).unwrap()
+        }))
+    }
+}
+
+fn receiver<'a, T>(
+    ctx: Context,
+    class: &Class,
+    spec: &ClassSpec<T>,
+    object: JSObjectRef,
+) -> Result<&'a NativeData, String> {
+    unsafe { NativeData::from_object(ctx, class, object) }
+        .ok_or_else(|| String::new(&format!("object is not an instance of {}", spec.name)).unwrap())
+}
+
+fn already_borrowed<T>(spec: &ClassSpec<T>) -> String {
+    String::new(&format!("{} instance is already in use", spec.name)).unwrap()
+}
+
+fn not_registered() -> String {
+    String::new("class is not registered with this context group").unwrap()
+}
+
+unsafe fn throw(ctx: Context, exception: *mut JSValueRef, error: String) {
+    if !exception.is_null() {
+        *exception = error.to_js_value(&ctx);
+    }
+}
+
+unsafe fn arguments(ctx: Context, count: usize, arguments: *const JSValueRef) -> Vec<Value> {
+    if count == 0 {
+        return vec![];
+    }
+    std::slice::from_raw_parts(arguments, count)
+        .iter()
+        .map(|v| Value::from(ctx, *v))
+        .collect()
+}
+
+unsafe extern "C" fn get_value<T: 'static>(
+    ctx: JSContextRef,
+    object: JSObjectRef,
+    name: JSStringRef,
+    exception: *mut JSValueRef,
+) -> JSValueRef {
+    let ctx = Context(ctx);
+    let (class, spec) = match native_class::<T>(ctx) {
+        Some(native) => native,
+        None => return null(),
+    };
+    let name = std::string::String::from(&String::retain(name));
+    let value = match spec.values.get(&name) {
+        Some(value) => value,
+        None => return null(),
+    };
+
+    let result = receiver(ctx, &class, &spec, object).and_then(|data| {
+        let this = data.borrow::<T>().ok_or_else(|| already_borrowed(&spec))?;
+        guard(|| (value.get)(ctx, &this))
+    });
+
+    match result {
+        Ok(v) => v.0,
+        Err(e) => {
+            throw(ctx, exception, e);
+            JSValueMakeUndefined(ctx.0)
+        }
+    }
+}
+
+unsafe extern "C" fn set_value<T: 'static>(
+    ctx: JSContextRef,
+    object: JSObjectRef,
+    name: JSStringRef,
+    value: JSValueRef,
+    exception: *mut JSValueRef,
+) -> bool {
+    let ctx = Context(ctx);
+    let (class, spec) = match native_class::<T>(ctx) {
+        Some(native) => native,
+        None => return false,
+    };
+    let name = std::string::String::from(&String::retain(name));
+    let setter = match spec.values.get(&name).and_then(|v| v.set.as_ref()) {
+        Some(setter) => setter,
+        None => return false,
+    };
+
+    let result = receiver(ctx, &class, &spec, object).and_then(|data| {
+        let mut this = data
+            .borrow_mut::<T>()
+            .ok_or_else(|| already_borrowed(&spec))?;
+        guard(|| setter(ctx, &mut this, Value::from(ctx, value)))
+    });
+
+    if let Err(e) = result {
+        throw(ctx, exception, e);
+    }
+    true
+}
+
+unsafe extern "C" fn construct<T: Send + 'static>(
+    ctx: JSContextRef,
+    constructor: JSObjectRef,
+    argument_count: usize,
+    argument_values: *const JSValueRef,
+    exception: *mut JSValueRef,
+) -> JSObjectRef {
+    let ctx = Context(ctx);
+    let (class, spec) = match native_class::<T>(ctx) {
+        Some(native) => native,
+        None => {
+            throw(ctx, exception, not_registered());
+            return null_mut();
+        }
+    };
+    let args = arguments(ctx, argument_count, argument_values);
+
+    let value = match spec.constructor.as_ref().map(|c| guard(|| c(ctx, args))) {
+        Some(Ok(value)) => value,
+        Some(Err(e)) => {
+            throw(ctx, exception, e);
+            return null_mut();
+        }
+        None => {
+            let message = format!("{} is not a constructor", spec.name);
+            throw(ctx, exception, String::new(&message).unwrap());
+            return null_mut();
+        }
+    };
+
+    // Take the prototype from the constructor, as `new` does for JS functions.
+    let instance = instantiate(ctx, &class, spec.clone(), value);
+    let key = String::new("prototype").unwrap();
+    let prototype = JSObjectGetProperty(ctx.0, constructor, *key, null_mut());
+    if !prototype.is_null() && JSValueIsObject(ctx.0, prototype) {
+        JSObjectSetPrototype(ctx.0, instance, prototype);
+    }
+    instance
+}
+
+unsafe extern "C" fn initialize<T: 'static>(ctx: JSContextRef, object: JSObjectRef) {
+    let ctx = Context(ctx);
+    let (class, spec) = match native_class::<T>(ctx) {
+        Some(native) => native,
+        None => return,
+    };
+    let initialize = match &spec.initialize {
+        Some(initialize) => initialize,
+        None => return,
+    };
+    if let Some(data) = NativeData::from_object(ctx, &class, object) {
+        if let Some(mut this) = data.borrow_mut::<T>() {
+            let _ = guard(|| {
+                initialize(ctx, Object(ctx, object), &mut this);
+                Ok(())
+            });
+        }
+    }
+}
+
+// Only set on classes without a native parent: JavaScriptCore finalizes the
+// most derived class first, so the base class is the last to see the data.
+// The value and the finalize hook are left for `collect_garbage`.
+unsafe extern "C" fn finalize(object: JSObjectRef) {
+    let data = JSObjectGetPrivate(object) as *mut NativeData;
+    if data.is_null() {
+        return;
+    }
+    let data = Box::from_raw(data);
+    let group = data.group;
+    defer(
+        group,
+        Garbage::new(move || {
+            let NativeData { value, spec, .. } = *data;
+            spec.finalize(value.into_inner());
+        }),
+    );
+}
+
+unsafe extern "C" fn has_instance<T: 'static>(
+    ctx: JSContextRef,
+    constructor: JSObjectRef,
+    possible_instance: JSValueRef,
+    exception: *mut JSValueRef,
+) -> bool {
+    let ctx = Context(ctx);
+    let spec = match native_class::<T>(ctx) {
+        Some((_, spec)) => spec,
+        None => return false,
+    };
+    let has_instance = match &spec.has_instance {
+        Some(has_instance) => has_instance,
+        None => return false,
+    };
+
+    let result = guard(|| {
+        has_instance(
+            ctx,
+            Object(ctx, constructor),
+            Value::from(ctx, possible_instance),
+        )
+    });
+    match result {
+        Ok(v) => v,
+        Err(e) => {
+            throw(ctx, exception, e);
+            false
+        }
+    }
+}
+
+unsafe extern "C" fn convert_to_type<T: 'static>(
+    ctx: JSContextRef,
+    object: JSObjectRef,
+    ty: JSType,
+    exception: *mut JSValueRef,
+) -> JSValueRef {
+    let ctx = Context(ctx);
+    let ty = match ValueType::from_raw(ty) {
+        Some(ty) => ty,
+        None => return null(),
+    };
+    let (class, spec) = match native_class::<T>(ctx) {
+        Some(native) => native,
+        None => return null(),
+    };
+    let convert_to_type = match &spec.convert_to_type {
+        Some(convert_to_type) => convert_to_type,
+        None => return null(),
+    };
+
+    let result = receiver(ctx, &class, &spec, object).and_then(|data| {
+        let this = data.borrow::<T>().ok_or_else(|| already_borrowed(&spec))?;
+        guard(|| convert_to_type(ctx, &this, ty))
+    });
+
+    match result {
+        Ok(Some(v)) => v.0,
+        // Fall back to the default conversion.
+        Ok(None) => null(),
+        Err(e) => {
+            throw(ctx, exception, e);
+            null()
+        }
+    }
+}
+
+/// Builds a JavaScript class whose instances own a Rust value of type `T`.
+///
+/// Instances are created with `ContextType::make_instance` once the class has
+/// been registered with a context's group. Contexts can be used from any
+/// thread, so `T` must be `Send`.
+pub struct ClassBuilder<T> {
+    spec: ClassSpec<T>,
+    parent: Option<ParentLink>,
+}
+
+impl<T: 'static> ClassBuilder<T> {
+    pub fn new(name: &str) -> ClassBuilder<T> {
+        ClassBuilder {
+            spec: ClassSpec {
+                name: name.to_string(),
+                values: HashMap::new(),
+                methods: HashMap::new(),
+                constructor: None,
+                initialize: None,
+                finalize: None,
+                has_instance: None,
+                convert_to_type: None,
+                upcast: None,
+            },
+            parent: None,
+        }
+    }
+
+    /// Inherits the static values and methods of the native class for `P`,
+    /// which must already be registered. Inherited members reach the `P`
+    /// inside each instance through `AsRef` and `AsMut`.
+    pub fn parent<P: 'static>(mut self) -> ClassBuilder<T>
+    where
+        T: AsRef<P> + AsMut<P>,
+    {
+        self.parent = Some(ParentLink {
+            type_id: TypeId::of::<P>(),
+            link: |grandparent| {
+                Arc::new(Parent::<T, P> {
+                    grandparent,
+                    _marker: PhantomData,
+                })
+            },
+        });
+        self
+    }
+
+    /// Adds a read-only static value.
+    pub fn getter<G>(mut self, name: &str, get: G) -> ClassBuilder<T>
+    where
+        G: Fn(Context, &T) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        let value = StaticValue {
+            get: Box::new(get),
+            set: None,
+        };
+        self.spec.values.insert(name.to_string(), value);
+        self
+    }
+
+    /// Adds a writable static value.
+    pub fn accessor<G, S>(mut self, name: &str, get: G, set: S) -> ClassBuilder<T>
+    where
+        G: Fn(Context, &T) -> Result<Value, String> + Send + Sync + 'static,
+        S: Fn(Context, &mut T, Value) -> Result<(), String> + Send + Sync + 'static,
+    {
+        let value = StaticValue {
+            get: Box::new(get),
+            set: Some(Box::new(set)),
+        };
+        self.spec.values.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn method<F>(mut self, name: &str, method: F) -> ClassBuilder<T>
+    where
+        F: Fn(Context, &mut T, Vec<Value>) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.spec.methods.insert(name.to_string(), Box::new(method));
+        self
+    }
+
+    /// Makes the class constructible from JavaScript with `new`; see
+    /// `ContextType::class_constructor`.
+    pub fn constructor<F>(mut self, constructor: F) -> ClassBuilder<T>
+    where
+        F: Fn(Context, Vec<Value>) -> Result<T, String> + Send + Sync + 'static,
+    {
+        self.spec.constructor = Some(Box::new(constructor));
+        self
+    }
+
+    /// Called when an instance is created, after it owns its value.
+    pub fn initialize<F>(mut self, initialize: F) -> ClassBuilder<T>
+    where
+        F: Fn(Context, Object, &mut T) + Send + Sync + 'static,
+    {
+        self.spec.initialize = Some(Box::new(initialize));
+        self
+    }
+
+    /// Called with the owned value when an instance is garbage collected.
+    /// Without a finalizer the value is simply dropped.
+    pub fn finalize<F>(mut self, finalize: F) -> ClassBuilder<T>
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        self.spec.finalize = Some(Box::new(finalize));
+        self
+    }
+
+    /// Answers `value instanceof object` for instances of this class.
+    pub fn has_instance<F>(mut self, has_instance: F) -> ClassBuilder<T>
+    where
+        F: Fn(Context, Object, Value) -> Result<bool, String> + Send + Sync + 'static,
+    {
+        self.spec.has_instance = Some(Box::new(has_instance));
+        self
+    }
+
+    /// Converts instances to a primitive of the requested type. Returning
+    /// `Ok(None)` falls back to the default conversion.
+    pub fn convert_to_type<F>(mut self, convert_to_type: F) -> ClassBuilder<T>
+    where
+        F: Fn(Context, &T, ValueType) -> Result<Option<Value>, String> + Send + Sync + 'static,
+    {
+        self.spec.convert_to_type = Some(Box::new(convert_to_type));
+        self
+    }
+
+    pub fn register<C: ContextType>(self, ctx: &C) -> Result<(), ClassError> {
+        let ctx = Context(unsafe { ctx.as_ptr() });
+        let ClassBuilder { mut spec, parent } = self;
+
+        let parent_type = parent.as_ref().map(|parent| parent.type_id);
+        let parent_class = match parent {
+            Some(parent) => {
+                let (class, upcast) = with_registry(ctx, |registry| {
+                    let native = registry.classes.get(&parent.type_id)?;
+                    Some((native.class.clone(), native.upcast.clone()))
+                })
+                .ok_or(ClassError::ParentNotRegistered)?;
+                spec.upcast = Some((parent.link)(upcast));
+                Some(class)
+            }
+            None => None,
+        };
+
+        let class_name = CString::new(spec.name.clone())
+            .map_err(|_| ClassError::InvalidName(spec.name.clone()))?;
+        let value_names = c_names(spec.values.keys())?;
+
+        let mut static_values = value_names
+            .iter()
+            .map(|name| {
+                let writable = spec.values[name.to_str().unwrap()].set.is_some();
+                JSStaticValue {
+                    name: name.as_ptr(),
+                    getProperty: Some(get_value::<T>),
+                    setProperty: if writable { Some(set_value::<T>) } else { None },
+                    attributes: if writable {
+                        kJSPropertyAttributeDontDelete as JSPropertyAttributes
+                    } else {
+                        (kJSPropertyAttributeReadOnly | kJSPropertyAttributeDontDelete)
+                            as JSPropertyAttributes
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        static_values.push(JSStaticValue {
+            name: null(),
+            getProperty: None,
+            setProperty: None,
+            attributes: 0,
+        });
+
+        // JavaScriptCore copies the names and tables while creating the class.
+        let definition = JSClassDefinition {
+            version: 0,
+            attributes: kJSClassAttributeNone as JSClassAttributes,
+            className: class_name.as_ptr(),
+            parentClass: parent_class.as_ref().map_or(null_mut(), |class| class.0),
+            staticValues: static_values.as_ptr(),
+            staticFunctions: null(),
+            initialize: if spec.initialize.is_some() {
+                Some(initialize::<T>)
+            } else {
+                None
+            },
+            finalize: if parent_class.is_none() {
+                Some(finalize)
+            } else {
+                None
+            },
+            hasProperty: None,
+            getProperty: None,
+            setProperty: None,
+            deleteProperty: None,
+            getPropertyNames: None,
+            callAsFunction: None,
+            callAsConstructor: None,
+            hasInstance: if spec.has_instance.is_some() {
+                Some(has_instance::<T>)
+            } else {
+                None
+            },
+            convertToType: if spec.convert_to_type.is_some() {
+                Some(convert_to_type::<T>)
+            } else {
+                None
+            },
+        };
+
+        with_registry(ctx, |registry| {
+            let type_id = TypeId::of::<T>();
+            if registry.classes.contains_key(&type_id) {
+                return Err(ClassError::AlreadyRegistered(spec.name.clone()));
+            }
+            let native = NativeClass {
+                class: Class::new(&definition),
+                upcast: spec.upcast.clone(),
+                parent: parent_type,
+                install_methods: install_methods::<T>,
+                spec: Arc::new(spec),
+            };
+            registry.classes.insert(type_id, native);
+            Ok(())
+        })?;
+
+        // JavaScriptCore does not retain a class's parent, so keep it alive
+        // for as long as any instance of the subclass might be.
+        std::mem::forget(parent_class);
+        Ok(())
+    }
+}
+
+fn c_names<'a, I>(names: I) -> Result<Vec<CString>, ClassError>
+where
+    I: Iterator<Item = &'a std::string::String>,
+{
+    names
+        .map(|name| CString::new(name.clone()).map_err(|_| ClassError::InvalidName(name.clone())))
+        .collect()
+}
@@ -1,9 +1,12 @@
-use crate::types::{Object, String, Value, ValueType};
+use crate::types::{Array, Context, ContextType, Exception, Object, String, Value, ValueType};
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
-use std::ptr::null_mut;
+use std::hash::BuildHasher;
+use std::ptr::{null, null_mut};
 
 use javascriptcore_sys::{
-    JSStringGetMaximumUTF8CStringSize, JSStringGetUTF8CString, JSValueToBoolean, JSValueToNumber,
+    JSObjectMake, JSStringGetCharactersPtr, JSStringGetLength, JSValueMakeBoolean, JSValueMakeNull,
+    JSValueMakeNumber, JSValueMakeUndefined, JSValueToBoolean, JSValueToNumber,
     JSValueToStringCopy,
 };
 
@@ -19,6 +22,11 @@ impl TryFrom<&Value> for std::string::String {
 #[derive(Debug, Clone)]
 pub enum TryFromValueError {
     InvalidConversion(ValueType),
+    NotAnArray,
+    LengthMismatch(usize, usize),
+    NotAnInteger(f64),
+    OutOfRange(std::string::String, &'static str),
+    Exception(Exception),
 }
 
 impl std::error::Error for TryFromValueError {}
@@ -26,6 +34,13 @@ impl std::fmt::Display for TryFromValueError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let v = match self {
             TryFromValueError::InvalidConversion(x) => format!("InvalidConversion({:?})", &x),
+            TryFromValueError::NotAnArray => "NotAnArray".into(),
+            TryFromValueError::LengthMismatch(expected, actual) => {
+                format!("LengthMismatch(expected {}, got {})", expected, actual)
+            }
+            TryFromValueError::NotAnInteger(n) => format!("NotAnInteger({})", n),
+            TryFromValueError::OutOfRange(n, ty) => format!("OutOfRange({} for {})", n, ty),
+            TryFromValueError::Exception(e) => format!("Exception({})", e),
         };
 
         write!(f, "{}", v)
@@ -60,6 +75,72 @@ impl TryFrom<&Value> for f64 {
     }
 }
 
+macro_rules! integer_conversions {
+    ($($ty:ident),*) => {
+        $(
+            impl TryFrom<&Value> for $ty {
+                type Error = TryFromValueError;
+
+                fn try_from(value: &Value) -> Result<$ty, Self::Error> {
+                    if let ValueType::BigInt = value.js_type() {
+                        let digits = bigint_digits(value)?;
+                        return digits.parse::<$ty>().map_err(|_| {
+                            TryFromValueError::OutOfRange(digits, stringify!($ty))
+                        });
+                    }
+
+                    let n = f64::try_from(value)?;
+                    if !n.is_finite() || n.trunc() != n {
+                        return Err(TryFromValueError::NotAnInteger(n));
+                    }
+                    // `MAX as f64` rounds up to a power of two for the wider
+                    // types, so only values below the next integer are in range.
+                    if n < $ty::min_value() as f64 || n >= $ty::max_value() as f64 + 1.0 {
+                        return Err(TryFromValueError::OutOfRange(n.to_string(), stringify!($ty)));
+                    }
+                    Ok(n as $ty)
+                }
+            }
+
+            impl FromValue for $ty {
+                fn from_value(value: &Value) -> Result<$ty, TryFromValueError> {
+                    $ty::try_from(value)
+                }
+            }
+        )*
+    };
+}
+
+// BigInts are read through their decimal representation, which needs no
+// BigInt-specific API.
+fn bigint_digits(value: &Value) -> Result<std::string::String, TryFromValueError> {
+    let mut exception = null();
+    let digits = unsafe { JSValueToStringCopy(*value.2, value.0, &mut exception) };
+    if exception.is_null() {
+        Ok(std::string::String::from(&String(digits)))
+    } else {
+        let exception = Exception::new(Value::from(value.2, exception));
+        Err(TryFromValueError::Exception(exception))
+    }
+}
+
+integer_conversions!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+/// ECMAScript `ToUint32`: truncates and wraps modulo 2^32, with NaN and the
+/// infinities becoming 0.
+pub(crate) fn to_uint32(n: f64) -> u32 {
+    const TWO_32: f64 = 4_294_967_296.0;
+    if !n.is_finite() {
+        return 0;
+    }
+    let n = n.trunc() % TWO_32;
+    if n < 0.0 {
+        (n + TWO_32) as u32
+    } else {
+        n as u32
+    }
+}
+
 impl TryFrom<&Value> for Object {
     type Error = TryFromValueError;
 
@@ -71,15 +152,28 @@ impl TryFrom<&Value> for Object {
     }
 }
 
+impl TryFrom<&Value> for Array {
+    type Error = TryFromValueError;
+
+    fn try_from(value: &Value) -> Result<Array, Self::Error> {
+        match value.js_type() {
+            ValueType::Object if value.is_array() => Ok(Array(Object(value.2, value.0 as _))),
+            ValueType::Object => Err(TryFromValueError::NotAnArray),
+            ty => Err(TryFromValueError::InvalidConversion(ty)),
+        }
+    }
+}
+
+// JavaScript strings are UTF-16 and may contain NULs and unpaired surrogates;
+// the latter become U+FFFD.
 impl From<&String> for std::string::String {
     fn from(string: &String) -> std::string::String {
-        let size = unsafe { JSStringGetMaximumUTF8CStringSize(string.0) };
-        let mut buffer = vec![0u8; size];
-        let written = unsafe { JSStringGetUTF8CString(string.0, buffer.as_mut_ptr() as _, size) };
-        buffer.truncate(written);
-
-        let c_str = unsafe { std::ffi::CStr::from_bytes_with_nul_unchecked(&*buffer) };
-        c_str.to_str().unwrap().to_string()
+        let len = unsafe { JSStringGetLength(string.0) };
+        if len == 0 {
+            return std::string::String::new();
+        }
+        let chars = unsafe { std::slice::from_raw_parts(JSStringGetCharactersPtr(string.0), len) };
+        std::string::String::from_utf16_lossy(chars)
     }
 }
 
@@ -96,3 +190,363 @@ impl TryFrom<&Value> for bool {
         }
     }
 }
+
+/// Conversion of Rust values into JavaScript values.
+///
+/// Integers are converted to numbers, so 64-bit integers beyond 2^53 lose
+/// precision. `None` becomes `null`, `()` becomes `undefined`, sequences and
+/// tuples become arrays and string-keyed maps become plain objects.
+pub trait IntoValue {
+    fn into_value(&self, ctx: Context) -> Value;
+}
+
+/// Fallible conversion of JavaScript values into Rust values.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, TryFromValueError>;
+}
+
+impl<'a, T: IntoValue + ?Sized> IntoValue for &'a T {
+    fn into_value(&self, ctx: Context) -> Value {
+        (**self).into_value(ctx)
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(&self, _ctx: Context) -> Value {
+        self.clone()
+    }
+}
+
+impl IntoValue for Object {
+    fn into_value(&self, _ctx: Context) -> Value {
+        self.to_js_value()
+    }
+}
+
+impl IntoValue for Array {
+    fn into_value(&self, _ctx: Context) -> Value {
+        self.to_js_value()
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(&self, ctx: Context) -> Value {
+        Value::from(ctx, self.to_js_value(&ctx))
+    }
+}
+
+impl IntoValue for str {
+    fn into_value(&self, ctx: Context) -> Value {
+        String::from(self).into_value(ctx)
+    }
+}
+
+impl IntoValue for std::string::String {
+    fn into_value(&self, ctx: Context) -> Value {
+        self.as_str().into_value(ctx)
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(&self, ctx: Context) -> Value {
+        Value::from(ctx, unsafe { JSValueMakeBoolean(*ctx, *self) })
+    }
+}
+
+macro_rules! number_into_value {
+    ($($ty:ty),*) => {
+        $(
+            impl IntoValue for $ty {
+                fn into_value(&self, ctx: Context) -> Value {
+                    Value::from(ctx, unsafe { JSValueMakeNumber(*ctx, *self as f64) })
+                }
+            }
+        )*
+    };
+}
+
+number_into_value!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+impl IntoValue for () {
+    fn into_value(&self, ctx: Context) -> Value {
+        Value::from(ctx, unsafe { JSValueMakeUndefined(*ctx) })
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(&self, ctx: Context) -> Value {
+        match self {
+            Some(value) => value.into_value(ctx),
+            None => Value::from(ctx, unsafe { JSValueMakeNull(*ctx) }),
+        }
+    }
+}
+
+/// Builds an array one element at a time. Each element is stored as soon as
+/// it is pushed, so the garbage collector can always reach it.
+pub(crate) struct ArrayBuilder {
+    array: Array,
+    len: u32,
+}
+
+impl ArrayBuilder {
+    pub(crate) fn new(ctx: Context) -> Result<ArrayBuilder, Exception> {
+        Ok(ArrayBuilder {
+            array: ctx.make_array(&[])?,
+            len: 0,
+        })
+    }
+
+    pub(crate) fn push(&mut self, value: Value) -> Result<(), Exception> {
+        self.array.set(self.len, value)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> Array {
+        self.array
+    }
+}
+
+/// The array `value` holds and its length.
+pub(crate) fn array_elements(value: &Value) -> Result<(Array, u32), TryFromValueError> {
+    let array = Array::try_from(value)?;
+    let length = array.len().map_err(TryFromValueError::Exception)?;
+    Ok((array, length))
+}
+
+/// The object's own enumerable string keys. Inherited keys are left out, as
+/// `JSON.stringify` leaves them out, so methods and the like from a
+/// prototype aren't read as entries.
+pub(crate) fn object_keys(object: &Object) -> Result<Vec<std::string::String>, Exception> {
+    let keys = object.own_enumerable_keys()?;
+    Ok(keys.iter().map(std::string::String::from).collect())
+}
+
+// A fresh array has no setters, so filling it only fails if the engine does.
+// The exception is then the closest thing to a result `IntoValue` can give.
+fn build_array<F>(ctx: Context, fill: F) -> Value
+where
+    F: FnOnce(&mut ArrayBuilder) -> Result<(), Exception>,
+{
+    let array = ArrayBuilder::new(ctx).and_then(|mut array| {
+        fill(&mut array)?;
+        Ok(array.finish())
+    });
+    match array {
+        Ok(array) => array.to_js_value(),
+        Err(e) => e.thrown_value(),
+    }
+}
+
+fn make_array<'a, T, I>(ctx: Context, items: I) -> Value
+where
+    T: IntoValue + ?Sized + 'a,
+    I: IntoIterator<Item = &'a T>,
+{
+    build_array(ctx, |array| {
+        for item in items {
+            array.push(item.into_value(ctx))?;
+        }
+        Ok(())
+    })
+}
+
+impl<T: IntoValue> IntoValue for [T] {
+    fn into_value(&self, ctx: Context) -> Value {
+        make_array(ctx, self)
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(&self, ctx: Context) -> Value {
+        make_array(ctx, self)
+    }
+}
+
+fn make_object<'a, T, I>(ctx: Context, entries: I) -> Value
+where
+    T: IntoValue + 'a,
+    I: IntoIterator<Item = (&'a std::string::String, &'a T)>,
+{
+    let object = Object(ctx, unsafe { JSObjectMake(*ctx, null_mut(), null_mut()) });
+    for (key, value) in entries {
+        // A fresh plain object has no setters that could throw.
+        let _ = object.set_property(&String::from(key.as_str()), value.into_value(ctx));
+    }
+    object.to_js_value()
+}
+
+impl<T: IntoValue, S: BuildHasher> IntoValue for HashMap<std::string::String, T, S> {
+    fn into_value(&self, ctx: Context) -> Value {
+        make_object(ctx, self)
+    }
+}
+
+impl<T: IntoValue> IntoValue for BTreeMap<std::string::String, T> {
+    fn into_value(&self, ctx: Context) -> Value {
+        make_object(ctx, self)
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Value, TryFromValueError> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for Object {
+    fn from_value(value: &Value) -> Result<Object, TryFromValueError> {
+        Object::try_from(value)
+    }
+}
+
+impl FromValue for Array {
+    fn from_value(value: &Value) -> Result<Array, TryFromValueError> {
+        Array::try_from(value)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<String, TryFromValueError> {
+        String::try_from(value)
+    }
+}
+
+impl FromValue for std::string::String {
+    fn from_value(value: &Value) -> Result<std::string::String, TryFromValueError> {
+        Ok(std::string::String::from(&String::try_from(value)?))
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<bool, TryFromValueError> {
+        bool::try_from(value)
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<f64, TryFromValueError> {
+        f64::try_from(value)
+    }
+}
+
+impl FromValue for f32 {
+    fn from_value(value: &Value) -> Result<f32, TryFromValueError> {
+        Ok(f64::try_from(value)? as f32)
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: &Value) -> Result<(), TryFromValueError> {
+        match value.js_type() {
+            ValueType::Undefined | ValueType::Null => Ok(()),
+            ty => Err(TryFromValueError::InvalidConversion(ty)),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Option<T>, TryFromValueError> {
+        match value.js_type() {
+            ValueType::Undefined | ValueType::Null => Ok(None),
+            _ => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Vec<T>, TryFromValueError> {
+        let (array, length) = array_elements(value)?;
+        (0..length)
+            .map(|i| T::from_value(&array.get(i).map_err(TryFromValueError::Exception)?))
+            .collect()
+    }
+}
+
+fn object_entries<T, C>(value: &Value) -> Result<C, TryFromValueError>
+where
+    T: FromValue,
+    C: std::iter::FromIterator<(std::string::String, T)>,
+{
+    let object = Object::try_from(value)?;
+    let keys = object_keys(&object).map_err(TryFromValueError::Exception)?;
+    keys.into_iter()
+        .map(|key| {
+            let value = object.get(&key).map_err(TryFromValueError::Exception)?;
+            Ok((key, T::from_value(&value)?))
+        })
+        .collect()
+}
+
+impl<T: FromValue, S: BuildHasher + Default> FromValue for HashMap<std::string::String, T, S> {
+    fn from_value(value: &Value) -> Result<HashMap<std::string::String, T, S>, TryFromValueError> {
+        object_entries(value)
+    }
+}
+
+impl<T: FromValue> FromValue for BTreeMap<std::string::String, T> {
+    fn from_value(value: &Value) -> Result<BTreeMap<std::string::String, T>, TryFromValueError> {
+        object_entries(value)
+    }
+}
+
+macro_rules! tuple_conversions {
+    ($len:expr => $($name:ident $index:tt),+) => {
+        impl<$($name: IntoValue),+> IntoValue for ($($name,)+) {
+            fn into_value(&self, ctx: Context) -> Value {
+                build_array(ctx, |array| {
+                    $(array.push(self.$index.into_value(ctx))?;)+
+                    Ok(())
+                })
+            }
+        }
+
+        impl<$($name: FromValue),+> FromValue for ($($name,)+) {
+            fn from_value(value: &Value) -> Result<($($name,)+), TryFromValueError> {
+                let (array, length) = array_elements(value)?;
+                if length as usize != $len {
+                    return Err(TryFromValueError::LengthMismatch($len, length as usize));
+                }
+                let element = |index| array.get(index).map_err(TryFromValueError::Exception);
+                Ok(($($name::from_value(&element($index)?)?,)+))
+            }
+        }
+    };
+}
+
+tuple_conversions!(1 => A 0);
+tuple_conversions!(2 => A 0, B 1);
+tuple_conversions!(3 => A 0, B 1, C 2);
+tuple_conversions!(4 => A 0, B 1, C 2, D 3);
+tuple_conversions!(5 => A 0, B 1, C 2, D 3, E 4);
+tuple_conversions!(6 => A 0, B 1, C 2, D 3, E 4, F 5);
+tuple_conversions!(7 => A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+tuple_conversions!(8 => A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+#[cfg(test)]
+mod tests {
+    use super::to_uint32;
+
+    #[test]
+    fn to_uint32_maps_non_finite_numbers_to_zero() {
+        assert_eq!(to_uint32(std::f64::NAN), 0);
+        assert_eq!(to_uint32(std::f64::INFINITY), 0);
+        assert_eq!(to_uint32(std::f64::NEG_INFINITY), 0);
+    }
+
+    #[test]
+    fn to_uint32_wraps_modulo_2_32() {
+        assert_eq!(to_uint32(-1.0), 4_294_967_295);
+        assert_eq!(to_uint32(4_294_967_296.0), 0);
+        assert_eq!(to_uint32(4_294_967_297.0), 1);
+        assert_eq!(to_uint32(-4_294_967_296.0), 0);
+    }
+
+    #[test]
+    fn to_uint32_truncates_fractions() {
+        assert_eq!(to_uint32(1.9), 1);
+        assert_eq!(to_uint32(-1.5), 4_294_967_295);
+        assert_eq!(to_uint32(-0.5), 0);
+    }
+}
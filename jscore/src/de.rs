@@ -0,0 +1,283 @@
+use crate::convert::{array_elements, object_keys};
+use crate::ser::SerdeError;
+use crate::types::{Array, Object, String, Value, ValueType};
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use std::convert::TryFrom;
+
+/// Converts a JavaScript value into a Rust value.
+///
+/// Both `null` and `undefined` deserialize as `None` or unit, and enums may
+/// be given either as a variant name or as an object with a single key.
+pub fn from_value<T: DeserializeOwned>(value: &Value) -> Result<T, SerdeError> {
+    T::deserialize(Deserializer {
+        value: value.clone(),
+    })
+}
+
+struct Deserializer {
+    value: Value,
+}
+
+fn get_key(object: &Object, key: &str) -> Result<Value, SerdeError> {
+    let name = String::from(key);
+    object
+        .get_property(&name)
+        .map_err(|e| SerdeError::new(e.to_string()))
+}
+
+fn keys(object: &Object) -> Result<Vec<std::string::String>, SerdeError> {
+    object_keys(object).map_err(|e| SerdeError::new(e.to_string()))
+}
+
+fn visit_number<'de, V: Visitor<'de>>(n: f64, visitor: V) -> Result<V::Value, SerdeError> {
+    // Integral numbers are offered as integers so integer fields accept them.
+    if n.fract() == 0.0 {
+        if n >= 0.0 && n < 18_446_744_073_709_551_616.0 {
+            return visitor.visit_u64(n as u64);
+        }
+        if n < 0.0 && n >= -9_223_372_036_854_775_808.0 {
+            return visitor.visit_i64(n as i64);
+        }
+    }
+    visitor.visit_f64(n)
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = SerdeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.value.js_type() {
+            ValueType::Undefined | ValueType::Null => visitor.visit_unit(),
+            ValueType::Boolean => {
+                visitor.visit_bool(bool::try_from(&self.value).map_err(SerdeError::new)?)
+            }
+            ValueType::Number => visit_number(
+                f64::try_from(&self.value).map_err(SerdeError::new)?,
+                visitor,
+            ),
+            ValueType::String => visitor
+                .visit_string(std::string::String::try_from(&self.value).map_err(SerdeError::new)?),
+            ValueType::Symbol => Err(SerdeError::new("cannot deserialize a symbol")),
+            ValueType::Unknown => Err(SerdeError::new(
+                "cannot deserialize a value of unknown type",
+            )),
+            ValueType::BigInt => {
+                if let Ok(n) = i64::try_from(&self.value) {
+                    visitor.visit_i64(n)
+                } else if let Ok(n) = u64::try_from(&self.value) {
+                    visitor.visit_u64(n)
+                } else if let Ok(n) = i128::try_from(&self.value) {
+                    visitor.visit_i128(n)
+                } else {
+                    visitor.visit_u128(u128::try_from(&self.value).map_err(SerdeError::new)?)
+                }
+            }
+            ValueType::Object => {
+                if self.value.is_array() {
+                    let (array, len) = array_elements(&self.value).map_err(SerdeError::new)?;
+                    visitor.visit_seq(ArrayAccess {
+                        array,
+                        index: 0,
+                        len,
+                    })
+                } else {
+                    let object = Object::try_from(&self.value).map_err(SerdeError::new)?;
+                    visitor.visit_map(ObjectAccess {
+                        keys: keys(&object)?.into_iter(),
+                        object,
+                        key: None,
+                    })
+                }
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, SerdeError> {
+        match self.value.js_type() {
+            ValueType::Undefined | ValueType::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        match self.value.js_type() {
+            ValueType::String => {
+                let variant =
+                    std::string::String::try_from(&self.value).map_err(SerdeError::new)?;
+                visitor.visit_enum(variant.into_deserializer())
+            }
+            ValueType::Object if !self.value.is_array() => {
+                let object = Object::try_from(&self.value).map_err(SerdeError::new)?;
+                let mut keys = keys(&object)?;
+                if keys.len() != 1 {
+                    return Err(SerdeError::new(
+                        "expected an object with a single key naming the variant",
+                    ));
+                }
+                let variant = keys.remove(0);
+                let value = get_key(&object, &variant)?;
+                visitor.visit_enum(EnumAccess { variant, value })
+            }
+            _ => Err(SerdeError::new(
+                "expected a string or an object with a single key naming the variant",
+            )),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+struct ArrayAccess {
+    array: Array,
+    index: u32,
+    len: u32,
+}
+
+impl<'de> de::SeqAccess<'de> for ArrayAccess {
+    type Error = SerdeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, SerdeError> {
+        if self.index >= self.len {
+            return Ok(None);
+        }
+        let index = self.index;
+        self.index += 1;
+
+        let value = self
+            .array
+            .get(index)
+            .map_err(|e| SerdeError::new(e.to_string()).at_index(index as usize))?;
+        seed.deserialize(Deserializer { value })
+            .map(Some)
+            .map_err(|e| e.at_index(index as usize))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some((self.len - self.index) as usize)
+    }
+}
+
+struct ObjectAccess {
+    object: Object,
+    keys: std::vec::IntoIter<std::string::String>,
+    key: Option<std::string::String>,
+}
+
+impl<'de> de::MapAccess<'de> for ObjectAccess {
+    type Error = SerdeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, SerdeError> {
+        match self.keys.next() {
+            Some(key) => {
+                let value = seed.deserialize(key.clone().into_deserializer())?;
+                self.key = Some(key);
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, SerdeError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| SerdeError::new("next_value_seed called before next_key_seed"))?;
+        let value = get_key(&self.object, &key).map_err(|e| e.at_key(&key))?;
+        seed.deserialize(Deserializer { value })
+            .map_err(|e| e.at_key(&key))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.keys.len())
+    }
+}
+
+struct EnumAccess {
+    variant: std::string::String,
+    value: Value,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = SerdeError;
+    type Variant = VariantAccess;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, VariantAccess), SerdeError> {
+        let variant = seed.deserialize(self.variant.clone().into_deserializer())?;
+        let access = VariantAccess {
+            variant: self.variant,
+            value: self.value,
+        };
+        Ok((variant, access))
+    }
+}
+
+struct VariantAccess {
+    variant: std::string::String,
+    value: Value,
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = SerdeError;
+
+    fn unit_variant(self) -> Result<(), SerdeError> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, SerdeError> {
+        let variant = self.variant;
+        seed.deserialize(Deserializer { value: self.value })
+            .map_err(|e| e.at_key(&variant))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        let variant = self.variant;
+        de::Deserializer::deserialize_seq(Deserializer { value: self.value }, visitor)
+            .map_err(|e| e.at_key(&variant))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, SerdeError> {
+        let variant = self.variant;
+        de::Deserializer::deserialize_map(Deserializer { value: self.value }, visitor)
+            .map_err(|e| e.at_key(&variant))
+    }
+}
@@ -0,0 +1,417 @@
+use crate::class;
+use crate::types::{
+    call_intrinsic, call_intrinsic_on, Array, ContextType, Object, String, Value, ValueType,
+};
+use javascriptcore_sys::*;
+use std::convert::TryFrom;
+use std::fmt::Write;
+use std::ptr::{null, null_mut};
+
+// Arrays, maps and sets longer than this are truncated.
+const MAX_ITEMS: usize = 100;
+
+const BOLD: u8 = 1;
+const GREEN: u8 = 32;
+const YELLOW: u8 = 33;
+const MAGENTA: u8 = 35;
+const CYAN: u8 = 36;
+const GREY: u8 = 90;
+
+#[derive(Debug, Clone)]
+pub struct InspectOptions {
+    /// How many levels of nested objects to show before abbreviating them as
+    /// `[Object]` or `[Array]`.
+    pub depth: usize,
+    /// Whether to color the output with ANSI escape codes.
+    pub colors: bool,
+}
+
+impl Default for InspectOptions {
+    fn default() -> InspectOptions {
+        InspectOptions {
+            depth: 2,
+            colors: false,
+        }
+    }
+}
+
+/// Formats a value the way Node's `util.inspect` does, e.g.
+/// `{ name: 'jscore', tags: [ 'js', 'rust' ] }`.
+pub fn inspect(value: &Value, options: &InspectOptions) -> std::string::String {
+    let mut inspector = Inspector {
+        options,
+        seen: Vec::new(),
+        out: std::string::String::new(),
+    };
+    inspector.value(value, 0);
+    inspector.out
+}
+
+struct Inspector<'a> {
+    options: &'a InspectOptions,
+    // Objects currently being formatted, to detect cycles.
+    seen: Vec<JSObjectRef>,
+    out: std::string::String,
+}
+
+impl<'a> Inspector<'a> {
+    fn paint(&mut self, color: u8, text: &str) {
+        if self.options.colors {
+            let _ = write!(self.out, "\x1b[{}m{}\x1b[0m", color, text);
+        } else {
+            self.out.push_str(text);
+        }
+    }
+
+    fn value(&mut self, value: &Value, depth: usize) {
+        match value.js_type() {
+            ValueType::Undefined => self.paint(GREY, "undefined"),
+            ValueType::Null => self.paint(BOLD, "null"),
+            ValueType::Boolean => {
+                let b = bool::try_from(value).unwrap_or_default();
+                self.paint(YELLOW, if b { "true" } else { "false" })
+            }
+            ValueType::Number => {
+                let n = f64::try_from(value).unwrap_or(std::f64::NAN);
+                // ToString drops the sign of negative zero.
+                if n == 0.0 && n.is_sign_negative() {
+                    self.paint(YELLOW, "-0")
+                } else {
+                    self.paint(YELLOW, &to_string_copy(value).unwrap_or_default())
+                }
+            }
+            ValueType::BigInt => {
+                let digits = to_string_copy(value).unwrap_or_default();
+                self.paint(YELLOW, &format!("{}n", digits))
+            }
+            ValueType::String => {
+                let s = std::string::String::try_from(value).unwrap_or_default();
+                self.paint(GREEN, &quote(&s))
+            }
+            ValueType::Symbol => {
+                let description = value.description().unwrap_or_default();
+                self.paint(GREEN, &format!("Symbol({})", description))
+            }
+            ValueType::Object => match Object::try_from(value) {
+                Ok(object) => self.object(&object, depth),
+                Err(_) => self.paint(CYAN, "[Object]"),
+            },
+            ValueType::Unknown => self.paint(CYAN, "[unknown]"),
+        }
+    }
+
+    fn object(&mut self, object: &Object, depth: usize) {
+        if self.seen.contains(&object.1) {
+            return self.paint(CYAN, "[Circular]");
+        }
+
+        let ctx = object.0;
+        if object.is_function() {
+            return self.function(object);
+        }
+        if unsafe { JSValueIsDate(*ctx, object.1) } {
+            return self.date(object);
+        }
+        if is_instance_of(object, "Error") {
+            return self.error(object);
+        }
+
+        let kind = if unsafe { JSValueIsArray(*ctx, object.1) } {
+            "Array"
+        } else if is_instance_of(object, "Map") {
+            "Map"
+        } else if is_instance_of(object, "Set") {
+            "Set"
+        } else {
+            "Object"
+        };
+        if depth > self.options.depth {
+            return self.paint(CYAN, &format!("[{}]", kind));
+        }
+
+        self.seen.push(object.1);
+        match kind {
+            "Array" => self.array(object, depth),
+            "Map" => self.collection(object, depth, true),
+            "Set" => self.collection(object, depth, false),
+            _ => self.properties(object, depth),
+        }
+        self.seen.pop();
+    }
+
+    fn function(&mut self, function: &Object) {
+        let text = match get_string(function, "name") {
+            Some(ref name) if !name.is_empty() => format!("[Function: {}]", name),
+            _ => "[Function (anonymous)]".into(),
+        };
+        self.paint(CYAN, &text)
+    }
+
+    fn date(&mut self, date: &Object) {
+        // `toISOString` throws for invalid dates.
+        let text = call_intrinsic_on(date.0, "Date.prototype.toISOString", date.1, &[])
+            .ok()
+            .and_then(|iso| std::string::String::try_from(&iso).ok())
+            .unwrap_or_else(|| "Invalid Date".into());
+        self.paint(MAGENTA, &text)
+    }
+
+    fn error(&mut self, error: &Object) {
+        let header = to_string_copy(&error.to_js_value()).unwrap_or_else(|| "Error".into());
+        self.out.push_str(&header);
+        if let Some(stack) = get_string(error, "stack") {
+            for frame in stack.lines().filter(|frame| !frame.is_empty()) {
+                self.out.push_str("\n    at ");
+                self.out.push_str(frame);
+            }
+        }
+    }
+
+    fn array(&mut self, array: &Object, depth: usize) {
+        let length = own_value(array, "length")
+            .and_then(|length| f64::try_from(&length).ok())
+            .map_or(0, |length| length as usize);
+        if length == 0 {
+            return self.out.push_str("[]");
+        }
+
+        self.out.push_str("[ ");
+        for index in 0..length.min(MAX_ITEMS) {
+            if index > 0 {
+                self.out.push_str(", ");
+            }
+            let name = String::from(index.to_string().as_str());
+            self.property(own_property(array, &name), depth);
+        }
+        self.more_items(length);
+        self.out.push_str(" ]");
+    }
+
+    fn collection(&mut self, collection: &Object, depth: usize, is_map: bool) {
+        let kind = if is_map { "Map" } else { "Set" };
+        let items = match collection_items(collection, is_map) {
+            Some(items) => items,
+            None => return self.properties(collection, depth),
+        };
+
+        // Map entries are flattened into alternating keys and values.
+        let stride = if is_map { 2 } else { 1 };
+        let size = items.len().map_or(0, |length| length as usize / stride);
+        let _ = write!(self.out, "{}({}) {{", kind, size);
+        for index in 0..size.min(MAX_ITEMS) {
+            self.out.push_str(if index > 0 { ", " } else { " " });
+            let item = |offset| items.get((index * stride + offset) as u32).ok();
+            if !is_map {
+                if let Some(value) = item(0) {
+                    self.value(&value, depth + 1);
+                }
+                continue;
+            }
+            if let (Some(key), Some(value)) = (item(0), item(1)) {
+                self.value(&key, depth + 1);
+                self.out.push_str(" => ");
+                self.value(&value, depth + 1);
+            }
+        }
+        self.more_items(size);
+        self.out.push_str(if size > 0 { " }" } else { "}" });
+    }
+
+    fn properties(&mut self, object: &Object, depth: usize) {
+        match object.prototype() {
+            Ok(ref prototype) if prototype.js_type() == ValueType::Null => {
+                self.out.push_str("[Object: null prototype] ")
+            }
+            Ok(prototype) => {
+                let name = Object::try_from(&prototype)
+                    .ok()
+                    .and_then(|prototype| own_value(&prototype, "constructor"))
+                    .and_then(|constructor| Object::try_from(&constructor).ok())
+                    .and_then(|constructor| own_value(&constructor, "name"))
+                    .and_then(|name| std::string::String::try_from(&name).ok());
+                match name {
+                    Some(ref name) if !name.is_empty() && name != "Object" => {
+                        let _ = write!(self.out, "{} ", name);
+                    }
+                    _ => {}
+                }
+            }
+            Err(_) => {}
+        }
+
+        let names = match object.own_enumerable_keys() {
+            Ok(names) => names,
+            Err(_) => return self.paint(CYAN, "[Thrown]"),
+        };
+        if names.is_empty() {
+            return self.out.push_str("{}");
+        }
+
+        self.out.push_str("{ ");
+        for (index, name) in names.iter().enumerate() {
+            if index > 0 {
+                self.out.push_str(", ");
+            }
+            let key = std::string::String::from(name);
+            if is_identifier(&key) {
+                self.out.push_str(&key);
+            } else {
+                let key = quote(&key);
+                self.paint(GREEN, &key);
+            }
+            self.out.push_str(": ");
+            self.property(own_property(object, name), depth);
+        }
+        self.out.push_str(" }");
+    }
+
+    fn property(&mut self, property: Option<Property>, depth: usize) {
+        match property {
+            Some(Property::Value(value)) => self.value(&value, depth + 1),
+            Some(Property::Accessor(accessor)) => self.paint(CYAN, accessor),
+            Some(Property::Missing) => self.paint(GREY, "<empty item>"),
+            None => self.paint(CYAN, "[Thrown]"),
+        }
+    }
+
+    fn more_items(&mut self, length: usize) {
+        if length > MAX_ITEMS {
+            let more = length - MAX_ITEMS;
+            let _ = write!(
+                self.out,
+                ", ... {} more item{}",
+                more,
+                if more == 1 { "" } else { "s" }
+            );
+        }
+    }
+}
+
+enum Property {
+    Value(Value),
+    Accessor(&'static str),
+    Missing,
+}
+
+// Reads an own property from its descriptor, so getters, which may call back
+// into the host, are never run.
+fn own_property(object: &Object, name: &String) -> Option<Property> {
+    let ctx = object.0;
+    let args = [object.1 as JSValueRef, name.to_js_value(&ctx)];
+    let descriptor = call_intrinsic(ctx, "Object.getOwnPropertyDescriptor", &args).ok()?;
+    if descriptor.js_type() == ValueType::Undefined {
+        return Some(Property::Missing);
+    }
+    let descriptor = Object::try_from(&descriptor).ok()?;
+    // Descriptors are fresh objects; without a prototype, reading a missing
+    // field can't run anything a script put on `Object.prototype`.
+    unsafe { JSObjectSetPrototype(*ctx, descriptor.1, JSValueMakeNull(*ctx)) };
+
+    let has = |field: &str| {
+        let value = descriptor.get(field).ok();
+        value.map_or(false, |value| value.js_type() != ValueType::Undefined)
+    };
+    match (has("get"), has("set")) {
+        (true, true) => Some(Property::Accessor("[Getter/Setter]")),
+        (true, false) => Some(Property::Accessor("[Getter]")),
+        (false, true) => Some(Property::Accessor("[Setter]")),
+        (false, false) => descriptor.get("value").ok().map(Property::Value),
+    }
+}
+
+fn own_value(object: &Object, name: &str) -> Option<Value> {
+    match own_property(object, &String::from(name))? {
+        Property::Value(value) => Some(value),
+        Property::Accessor(_) | Property::Missing => None,
+    }
+}
+
+fn to_string_copy(value: &Value) -> Option<std::string::String> {
+    let mut exception = null();
+    let s = unsafe { JSValueToStringCopy(*value.2, value.0, &mut exception) };
+    if exception.is_null() && !s.is_null() {
+        Some(std::string::String::from(&String(s)))
+    } else {
+        None
+    }
+}
+
+fn get_string(object: &Object, name: &str) -> Option<std::string::String> {
+    let value = object.get(name).ok()?;
+    std::string::String::try_from(&value).ok()
+}
+
+// Collects a map's or set's entries with the captured `forEach`, which
+// doesn't go through `size` or the iterator protocol, either of which a
+// script may have replaced. The entries are pushed onto an array passed as
+// `this`, so they stay reachable by the garbage collector.
+fn collection_items(collection: &Object, is_map: bool) -> Option<Array> {
+    let ctx = collection.0;
+    let items = ctx.make_array(&[]).ok()?;
+    let name = String::from("collect");
+    let collect = collection.make_function_with_callback(&name, move |ctx, this, args| {
+        let items = Array(this);
+        let mut args = args.into_iter();
+        let value = args.next();
+        let key = args.next();
+        let entry = if is_map {
+            vec![key, value]
+        } else {
+            vec![value]
+        };
+        for item in entry {
+            let item = item.unwrap_or_else(|| ctx.undefined());
+            items.push(item).map_err(|e| e.to_string())?;
+        }
+        Ok(ctx.undefined())
+    });
+    let for_each = if is_map {
+        "Map.prototype.forEach"
+    } else {
+        "Set.prototype.forEach"
+    };
+    let args = [collect.1 as JSValueRef, items.to_js_value().0];
+    call_intrinsic_on(ctx, for_each, collection.1, &args).ok()?;
+    Some(items)
+}
+
+// Checks against the constructor captured when the context was created, so
+// replacing, say, the global `Map` doesn't change how maps are shown.
+fn is_instance_of(object: &Object, constructor: &str) -> bool {
+    let ctx = object.0;
+    let constructor = class::intrinsic(ctx, constructor);
+    if constructor.is_null() || !unsafe { JSValueIsObject(*ctx, constructor) } {
+        return false;
+    }
+    let constructor = constructor as JSObjectRef;
+    unsafe { JSValueIsInstanceOfConstructor(*ctx, object.1, constructor, null_mut()) }
+}
+
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' || c == '$' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+fn quote(s: &str) -> std::string::String {
+    let mut quoted = std::string::String::with_capacity(s.len() + 2);
+    quoted.push('\'');
+    for c in s.chars() {
+        match c {
+            '\'' => quoted.push_str("\\'"),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\x{:02X}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
@@ -1,4 +1,16 @@
 #![feature(async_await)]
 
+pub mod class;
 pub mod convert;
+#[cfg(feature = "serde")]
+mod de;
+pub mod inspect;
+#[cfg(feature = "serde")]
+mod ser;
+pub mod stack;
 pub mod types;
+
+#[cfg(feature = "serde")]
+pub use crate::de::from_value;
+#[cfg(feature = "serde")]
+pub use crate::ser::{to_value, SerdeError};
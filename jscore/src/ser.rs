@@ -0,0 +1,672 @@
+use crate::convert::ArrayBuilder;
+use crate::types::{Context, ContextType, Object, String, Value};
+use javascriptcore_sys::*;
+use serde::ser::{self, Serialize};
+use std::fmt;
+use std::ptr::null_mut;
+
+#[derive(Debug, Clone)]
+enum PathSegment {
+    Key(std::string::String),
+    Index(usize),
+}
+
+/// An error converting between Rust and JavaScript values, along with the
+/// path to the value that caused it.
+#[derive(Debug, Clone)]
+pub struct SerdeError {
+    path: Vec<PathSegment>,
+    message: std::string::String,
+}
+
+impl SerdeError {
+    pub(crate) fn new<T: fmt::Display>(message: T) -> SerdeError {
+        SerdeError {
+            path: vec![],
+            message: message.to_string(),
+        }
+    }
+
+    pub(crate) fn at_key(mut self, key: &str) -> SerdeError {
+        self.path.insert(0, PathSegment::Key(key.to_string()));
+        self
+    }
+
+    pub(crate) fn at_index(mut self, index: usize) -> SerdeError {
+        self.path.insert(0, PathSegment::Index(index));
+        self
+    }
+
+    /// The path to the offending value, such as `items[2].name`. Empty for
+    /// the top-level value.
+    pub fn path(&self) -> std::string::String {
+        let mut path = std::string::String::new();
+        for segment in &self.path {
+            match segment {
+                PathSegment::Key(key) if path.is_empty() => path.push_str(key),
+                PathSegment::Key(key) => {
+                    path.push('.');
+                    path.push_str(key);
+                }
+                PathSegment::Index(index) => path.push_str(&format!("[{}]", index)),
+            }
+        }
+        path
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl std::error::Error for SerdeError {}
+impl fmt::Display for SerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path(), self.message)
+        }
+    }
+}
+
+impl ser::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> SerdeError {
+        SerdeError::new(msg)
+    }
+}
+
+impl serde::de::Error for SerdeError {
+    fn custom<T: fmt::Display>(msg: T) -> SerdeError {
+        SerdeError::new(msg)
+    }
+}
+
+/// Converts a Rust value into a native JavaScript value.
+///
+/// Structs and maps become plain objects, sequences and tuples become arrays,
+/// `None` and unit become `null`, and enums are externally tagged.
+pub fn to_value<C, T>(ctx: &C, value: &T) -> Result<Value, SerdeError>
+where
+    C: ContextType,
+    T: Serialize + ?Sized,
+{
+    let ctx = Context(unsafe { ctx.as_ptr() });
+    value.serialize(Serializer { ctx })
+}
+
+struct Serializer {
+    ctx: Context,
+}
+
+impl Serializer {
+    fn number(&self, n: f64) -> Value {
+        Value::from(self.ctx, unsafe { JSValueMakeNumber(self.ctx.0, n) })
+    }
+
+    fn string(&self, s: &str) -> Result<Value, SerdeError> {
+        let s = String::from(s);
+        Ok(Value::from(self.ctx, s.to_js_value(&self.ctx)))
+    }
+
+    // Numbers are doubles, so larger integers would silently lose precision.
+    fn integer(&self, n: i128) -> Result<Value, SerdeError> {
+        if n.abs() > MAX_SAFE_INTEGER {
+            return Err(SerdeError::new(format!(
+                "{} cannot be represented exactly as a JavaScript number",
+                n
+            )));
+        }
+        Ok(self.number(n as f64))
+    }
+}
+
+// `Number.MAX_SAFE_INTEGER`, the largest integer a double holds exactly.
+const MAX_SAFE_INTEGER: i128 = (1 << 53) - 1;
+
+fn make_object(ctx: Context) -> Object {
+    Object(ctx, unsafe { JSObjectMake(ctx.0, null_mut(), null_mut()) })
+}
+
+fn set_key(object: &Object, key: &str, value: Value) -> Result<(), SerdeError> {
+    let key = String::from(key);
+    object
+        .set_property(&key, value)
+        .map_err(|e| SerdeError::new(e.to_string()))
+}
+
+fn wrap_variant(ctx: Context, variant: &str, value: Value) -> Result<Value, SerdeError> {
+    let object = make_object(ctx);
+    set_key(&object, variant, value)?;
+    Ok(object.to_js_value())
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeArray;
+    type SerializeMap = SerializeObject;
+    type SerializeStruct = SerializeObject;
+    type SerializeStructVariant = SerializeObject;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, SerdeError> {
+        Ok(Value::from(self.ctx, unsafe {
+            JSValueMakeBoolean(self.ctx.0, v)
+        }))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, SerdeError> {
+        Ok(self.number(v as f64))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, SerdeError> {
+        Ok(self.number(v as f64))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, SerdeError> {
+        Ok(self.number(v as f64))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, SerdeError> {
+        self.integer(v.into())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, SerdeError> {
+        Ok(self.number(v as f64))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, SerdeError> {
+        Ok(self.number(v as f64))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, SerdeError> {
+        Ok(self.number(v as f64))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, SerdeError> {
+        self.integer(v.into())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, SerdeError> {
+        Ok(self.number(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, SerdeError> {
+        Ok(self.number(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, SerdeError> {
+        self.string(&v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, SerdeError> {
+        self.string(v)
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, SerdeError> {
+        let mut array = SerializeArray::new(self.ctx)?;
+        for byte in v {
+            array.push(byte)?;
+        }
+        array.finish()
+    }
+
+    fn serialize_none(self) -> Result<Value, SerdeError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, SerdeError> {
+        Ok(Value::from(self.ctx, unsafe {
+            JSValueMakeNull(self.ctx.0)
+        }))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, SerdeError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value, SerdeError> {
+        self.string(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, SerdeError> {
+        let ctx = self.ctx;
+        let value = value.serialize(self).map_err(|e| e.at_key(variant))?;
+        wrap_variant(ctx, variant, value)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SerializeArray, SerdeError> {
+        SerializeArray::new(self.ctx)
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, SerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeArray, SerdeError> {
+        let mut seq = self.serialize_seq(Some(len))?;
+        seq.variant = Some(variant);
+        Ok(seq)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeObject, SerdeError> {
+        Ok(SerializeObject {
+            ctx: self.ctx,
+            object: make_object(self.ctx),
+            next_key: None,
+            variant: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SerializeObject, SerdeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SerializeObject, SerdeError> {
+        let mut map = self.serialize_map(Some(len))?;
+        map.variant = Some(variant);
+        Ok(map)
+    }
+}
+
+struct SerializeArray {
+    ctx: Context,
+    array: ArrayBuilder,
+    len: usize,
+    variant: Option<&'static str>,
+}
+
+impl SerializeArray {
+    fn new(ctx: Context) -> Result<SerializeArray, SerdeError> {
+        Ok(SerializeArray {
+            ctx,
+            array: ArrayBuilder::new(ctx).map_err(|e| SerdeError::new(e.to_string()))?,
+            len: 0,
+            variant: None,
+        })
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        let index = self.len;
+        let value = value
+            .serialize(Serializer { ctx: self.ctx })
+            .map_err(|e| e.at_index(index))?;
+        self.array
+            .push(value)
+            .map_err(|e| SerdeError::new(e.to_string()).at_index(index))?;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, SerdeError> {
+        let value = self.array.finish().to_js_value();
+        match self.variant {
+            Some(variant) => wrap_variant(self.ctx, variant, value),
+            None => Ok(value),
+        }
+    }
+}
+
+impl ser::SerializeSeq for SerializeArray {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SerializeArray {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeArray {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SerializeArray {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        let variant = self.variant.unwrap_or_default();
+        self.push(value).map_err(|e| e.at_key(variant))
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+struct SerializeObject {
+    ctx: Context,
+    object: Object,
+    next_key: Option<std::string::String>,
+    variant: Option<&'static str>,
+}
+
+impl SerializeObject {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> Result<(), SerdeError> {
+        let value = value
+            .serialize(Serializer { ctx: self.ctx })
+            .map_err(|e| e.at_key(key))?;
+        set_key(&self.object, key, value)
+    }
+
+    fn finish(self) -> Result<Value, SerdeError> {
+        let value = self.object.to_js_value();
+        match self.variant {
+            Some(variant) => wrap_variant(self.ctx, variant, value),
+            None => Ok(value),
+        }
+    }
+}
+
+impl ser::SerializeMap for SerializeObject {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), SerdeError> {
+        self.next_key = Some(key.serialize(KeySerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerdeError> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| SerdeError::new("serialize_value called before serialize_key"))?;
+        self.insert(&key, value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for SerializeObject {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for SerializeObject {
+    type Ok = Value;
+    type Error = SerdeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), SerdeError> {
+        let variant = self.variant.unwrap_or_default();
+        self.insert(key, value).map_err(|e| e.at_key(variant))
+    }
+
+    fn end(self) -> Result<Value, SerdeError> {
+        self.finish()
+    }
+}
+
+/// Serializes map keys, which JavaScript objects only support as strings.
+struct KeySerializer;
+
+fn key_must_be_a_string() -> SerdeError {
+    SerdeError::new("map keys must be strings, numbers, booleans or unit variants")
+}
+
+impl ser::Serializer for KeySerializer {
+    type Ok = std::string::String;
+    type Error = SerdeError;
+
+    type SerializeSeq = ser::Impossible<std::string::String, SerdeError>;
+    type SerializeTuple = ser::Impossible<std::string::String, SerdeError>;
+    type SerializeTupleStruct = ser::Impossible<std::string::String, SerdeError>;
+    type SerializeTupleVariant = ser::Impossible<std::string::String, SerdeError>;
+    type SerializeMap = ser::Impossible<std::string::String, SerdeError>;
+    type SerializeStruct = ser::Impossible<std::string::String, SerdeError>;
+    type SerializeStructVariant = ser::Impossible<std::string::String, SerdeError>;
+
+    fn serialize_bool(self, v: bool) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<std::string::String, SerdeError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<std::string::String, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_none(self) -> Result<std::string::String, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(
+        self,
+        value: &T,
+    ) -> Result<std::string::String, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<std::string::String, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<std::string::String, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<std::string::String, SerdeError> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<std::string::String, SerdeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<std::string::String, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_seq(
+        self,
+        _len: Option<usize>,
+    ) -> Result<ser::Impossible<std::string::String, SerdeError>, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple(
+        self,
+        _len: usize,
+    ) -> Result<ser::Impossible<std::string::String, SerdeError>, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<ser::Impossible<std::string::String, SerdeError>, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<ser::Impossible<std::string::String, SerdeError>, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_map(
+        self,
+        _len: Option<usize>,
+    ) -> Result<ser::Impossible<std::string::String, SerdeError>, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<ser::Impossible<std::string::String, SerdeError>, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<ser::Impossible<std::string::String, SerdeError>, SerdeError> {
+        Err(key_must_be_a_string())
+    }
+}
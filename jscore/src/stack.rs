@@ -0,0 +1,224 @@
+use std::fmt;
+
+/// One line of a JavaScriptCore `stack` string, such as
+/// `handler@https://example.com/app.js:12:7` or `map@[native code]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    /// The function name, or `None` for anonymous functions. Top-level code
+    /// is named `global code`, `eval code` or `module code`.
+    pub function: Option<String>,
+    pub url: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    /// Whether the frame is a built-in function with no source location.
+    pub is_native: bool,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let function = self.function.as_ref().map_or("<anonymous>", String::as_str);
+        if self.is_native {
+            return write!(f, "{} (native)", function);
+        }
+        let url = match self.url {
+            Some(ref url) => url,
+            None => return f.write_str(function),
+        };
+        write!(f, "{} ({}", function, url)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        f.write_str(")")
+    }
+}
+
+/// Parses a JavaScriptCore `stack` string, innermost frame first.
+pub fn parse_stack(stack: &str) -> Vec<StackFrame> {
+    stack
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(parse_frame)
+        .collect()
+}
+
+fn parse_frame(line: &str) -> StackFrame {
+    // The function name comes first and can't contain `@`, but the URL can.
+    let (function, location) = match line.find('@') {
+        Some(at) => (&line[..at], &line[at + 1..]),
+        None if looks_like_location(line) => ("", line),
+        None => (line, ""),
+    };
+    let function = Some(function.to_string()).filter(|f| !f.is_empty());
+
+    if location == "[native code]" {
+        return StackFrame {
+            function,
+            url: None,
+            line: None,
+            column: None,
+            is_native: true,
+        };
+    }
+
+    let (url, line, column) = parse_location(location);
+    StackFrame {
+        function,
+        url: Some(url.to_string()).filter(|url| !url.is_empty()),
+        line,
+        column,
+        is_native: false,
+    }
+}
+
+// Splits `url:line:column`, taking the numbers from the end since URLs may
+// contain colons of their own.
+// A lone trailing number is taken as the port of a URL with no path, as in
+// `http://localhost:8080`. Once a line has been split off, the number before
+// it is always the line, so `http://localhost:5:6` is line 5, column 6.
+fn parse_location(location: &str) -> (&str, Option<u32>, Option<u32>) {
+    let (rest, last) = match split_number(location) {
+        Some((rest, _)) if is_bare_host(rest) => return (location, None, None),
+        Some(split) => split,
+        None => return (location, None, None),
+    };
+    match split_number(rest) {
+        Some((url, line)) => (url, Some(line), Some(last)),
+        None => (rest, Some(last), None),
+    }
+}
+
+fn split_number(location: &str) -> Option<(&str, u32)> {
+    let colon = location.rfind(':')?;
+    let number = location[colon + 1..].parse().ok()?;
+    Some((&location[..colon], number))
+}
+
+// Whether `location` is a URL's scheme and host with nothing after them.
+fn is_bare_host(location: &str) -> bool {
+    match location.find("://") {
+        Some(scheme) => !location[scheme + 3..].contains(|c| c == '/' || c == ':'),
+        None => false,
+    }
+}
+
+fn looks_like_location(line: &str) -> bool {
+    line.contains("://") || parse_location(line).1.is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_stack, StackFrame};
+
+    fn parse_one(line: &str) -> StackFrame {
+        let mut frames = parse_stack(line);
+        assert_eq!(frames.len(), 1);
+        frames.remove(0)
+    }
+
+    fn location(frame: &StackFrame) -> (Option<&str>, Option<u32>, Option<u32>) {
+        (
+            frame.url.as_ref().map(String::as_str),
+            frame.line,
+            frame.column,
+        )
+    }
+
+    #[test]
+    fn parses_named_frames() {
+        let frame = parse_one("handler@https://example.com/app.js:12:7");
+        assert_eq!(frame.function.as_ref().map(String::as_str), Some("handler"));
+        assert_eq!(
+            location(&frame),
+            (Some("https://example.com/app.js"), Some(12), Some(7))
+        );
+        assert!(!frame.is_native);
+    }
+
+    #[test]
+    fn keeps_at_signs_in_urls() {
+        let frame = parse_one("load@https://user@example.com/@scope/app.js:3:4");
+        assert_eq!(frame.function.as_ref().map(String::as_str), Some("load"));
+        let url = Some("https://user@example.com/@scope/app.js");
+        assert_eq!(location(&frame), (url, Some(3), Some(4)));
+    }
+
+    #[test]
+    fn keeps_ports_in_urls() {
+        let frame = parse_one("f@http://localhost:8080/app.js:5:6");
+        assert_eq!(
+            location(&frame),
+            (Some("http://localhost:8080/app.js"), Some(5), Some(6))
+        );
+
+        let frame = parse_one("f@http://localhost:8080");
+        assert_eq!(
+            location(&frame),
+            (Some("http://localhost:8080"), None, None)
+        );
+
+        let frame = parse_one("f@http://localhost:8080:5:6");
+        assert_eq!(
+            location(&frame),
+            (Some("http://localhost:8080"), Some(5), Some(6))
+        );
+    }
+
+    #[test]
+    fn reads_line_and_column_after_a_bare_host() {
+        let frame = parse_one("f@http://localhost:5:6");
+        assert_eq!(
+            location(&frame),
+            (Some("http://localhost"), Some(5), Some(6))
+        );
+    }
+
+    #[test]
+    fn parses_anonymous_frames() {
+        let frame = parse_one("@https://example.com/app.js:1:2");
+        assert_eq!(frame.function, None);
+        assert_eq!(
+            location(&frame),
+            (Some("https://example.com/app.js"), Some(1), Some(2))
+        );
+    }
+
+    #[test]
+    fn parses_lines_without_an_at_sign() {
+        let frame = parse_one("global code");
+        assert_eq!(
+            frame.function.as_ref().map(String::as_str),
+            Some("global code")
+        );
+        assert_eq!(location(&frame), (None, None, None));
+
+        let frame = parse_one("https://example.com/app.js:8:9");
+        assert_eq!(frame.function, None);
+        assert_eq!(
+            location(&frame),
+            (Some("https://example.com/app.js"), Some(8), Some(9))
+        );
+    }
+
+    #[test]
+    fn parses_native_frames() {
+        let frame = parse_one("map@[native code]");
+        assert_eq!(frame.function.as_ref().map(String::as_str), Some("map"));
+        assert_eq!(location(&frame), (None, None, None));
+        assert!(frame.is_native);
+        assert_eq!(frame.to_string(), "map (native)");
+    }
+
+    #[test]
+    fn skips_blank_lines() {
+        let frames = parse_stack("a@app.js:1:1\n\n  b@app.js:2:1\n");
+        let functions = frames
+            .iter()
+            .map(|frame| frame.function.as_ref().map(String::as_str))
+            .collect::<Vec<_>>();
+        assert_eq!(functions, vec![Some("a"), Some("b")]);
+    }
+}
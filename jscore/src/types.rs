@@ -1,8 +1,15 @@
+use crate::class::{self, ClassError};
+use crate::convert::{self, FromValue, IntoValue, TryFromValueError};
+use crate::inspect::{inspect, InspectOptions};
+use crate::stack::{self, StackFrame};
 use javascriptcore_sys::*;
+use std::any::TypeId;
+use std::cell::{Ref, RefMut};
 use std::convert::TryFrom;
 use std::ffi::CString;
 use std::ops::Deref;
 use std::ptr::{null, null_mut};
+use std::sync::{Mutex, TryLockError};
 
 macro_rules! retain_release {
     ($name:ident, $ffi_ref:ty, $retain_fn:tt, $drop_fn:tt) => {
@@ -37,60 +44,102 @@ unsafe impl Send for String {}
 unsafe impl Sync for String {}
 unsafe impl Send for Object {}
 unsafe impl Sync for Object {}
+unsafe impl Send for Array {}
+unsafe impl Sync for Array {}
 unsafe impl Send for ContextGroup {}
 unsafe impl Sync for ContextGroup {}
 unsafe impl Send for Value {}
 unsafe impl Sync for Value {}
+unsafe impl Send for Class {}
+unsafe impl Sync for Class {}
+unsafe impl Send for PropertyNameArray {}
+unsafe impl Sync for PropertyNameArray {}
 
 #[derive(Copy, Clone, Debug)]
 pub struct Context(pub(crate) JSContextRef);
 pub struct ContextGroup(pub(crate) JSContextGroupRef);
-pub struct GlobalContext(pub(crate) JSGlobalContextRef);
+pub struct GlobalContext(pub(crate) JSGlobalContextRef, pub(crate) ContextGroup);
+#[derive(Clone)]
 pub struct Object(pub(crate) Context, pub(crate) JSObjectRef);
+#[derive(Clone)]
+pub struct Array(pub(crate) Object);
 pub struct String(pub(crate) JSStringRef);
+pub struct Class(pub(crate) JSClassRef);
 
 use std::fmt;
 
 impl fmt::Debug for Object {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut s = f.debug_struct("Object");
+        fmt::Debug::fmt(&self.to_js_value(), f)
+    }
+}
 
-        unsafe {
-            let array = JSObjectCopyPropertyNames(*self.0, self.1);
-            let size = JSPropertyNameArrayGetCount(array);
-            for i in 0..size {
-                let js_ref = JSPropertyNameArrayGetNameAtIndex(array, i);
-                let prop_name = std::string::String::from(&String(js_ref));
-                let prop_value = Value::from(
-                    self.0,
-                    JSObjectGetPropertyAtIndex(*self.0, self.1, i as u32, null_mut()),
-                );
-                s.field(&prop_name, &format!("{:?}", prop_value));
-            }
+impl fmt::Debug for Array {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&inspect(self, &InspectOptions::default()))
+    }
+}
+
+// Like `console.log`, strings are written as is rather than quoted.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.js_type() {
+            ValueType::String => match std::string::String::try_from(self) {
+                Ok(s) => f.write_str(&s),
+                Err(_) => Err(fmt::Error),
+            },
+            _ => fmt::Debug::fmt(self, f),
         }
+    }
+}
 
-        s.finish()
+impl fmt::Debug for String {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&std::string::String::from(self), f)
     }
 }
 
 impl fmt::Debug for Exception {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("Exception")
-            .field("stack", &self.stack())
-            .field("message", &self.message())
+        f.debug_tuple("Exception")
+            .field(&self.thrown_value())
             .finish()
     }
 }
 
+impl std::error::Error for Exception {}
+
+// Errors are shown as `TypeError: message` followed by their stack, and any
+// other thrown value as `inspect` shows it.
 impl fmt::Display for Exception {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "Message: {}", &self.message())?;
-        writeln!(f, "Stack:")?;
-        write!(f, "{}", self.stack())
+        if !self.is_error() {
+            return fmt::Debug::fmt(&self.thrown_value(), f);
+        }
+        match (self.name(), self.message()) {
+            (Some(ref name), Some(ref message)) if !message.is_empty() => {
+                write!(f, "{}: {}", name, message)?
+            }
+            (Some(ref name), _) => f.write_str(name)?,
+            (None, Some(ref message)) => f.write_str(message)?,
+            (None, None) => f.write_str("Error")?,
+        }
+        if let Some(stack) = self.stack() {
+            for frame in stack.lines().filter(|frame| !frame.is_empty()) {
+                write!(f, "\n    at {}", frame)?;
+            }
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ValueType {
     Undefined,
     Null,
@@ -99,9 +148,35 @@ pub enum ValueType {
     String,
     Object,
     Symbol,
+    BigInt,
+    /// A type added to the engine after this crate was written.
+    Unknown,
+}
+
+/// Symbols the engine uses for its own protocols, found as properties of the
+/// global `Symbol` constructor.
+#[derive(Debug, Copy, Clone)]
+pub enum WellKnownSymbol {
+    AsyncIterator,
+    HasInstance,
+    Iterator,
+    ToPrimitive,
+    ToStringTag,
+}
+
+impl WellKnownSymbol {
+    fn name(self) -> &'static str {
+        match self {
+            WellKnownSymbol::AsyncIterator => "asyncIterator",
+            WellKnownSymbol::HasInstance => "hasInstance",
+            WellKnownSymbol::Iterator => "iterator",
+            WellKnownSymbol::ToPrimitive => "toPrimitive",
+            WellKnownSymbol::ToStringTag => "toStringTag",
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone)]
 pub struct Value(
     pub(crate) JSValueRef,
     pub(crate) ValueType,
@@ -116,6 +191,211 @@ pub trait ContextType {
         let value = unsafe { JSValueMakeUndefined(ptr) };
         Value(value, ValueType::Undefined, Context(ptr))
     }
+
+    /// Converts a Rust value into a JavaScript value, e.g. `ctx.value(&vec![1, 2, 3])`.
+    fn value<T: IntoValue + ?Sized>(&self, value: &T) -> Value {
+        let ptr = unsafe { self.as_ptr() };
+        value.into_value(Context(ptr))
+    }
+
+    /// Creates a new unique symbol, as `Symbol(description)` does.
+    fn symbol(&self, description: &str) -> Value {
+        let ptr = unsafe { self.as_ptr() };
+        let description = String::from(description);
+        let value = unsafe { JSValueMakeSymbol(ptr, *description) };
+        Value(value, ValueType::Symbol, Context(ptr))
+    }
+
+    /// Looks up a well-known symbol such as `Symbol.iterator`. Fails if a
+    /// script has removed or replaced the global `Symbol`.
+    fn well_known_symbol(&self, symbol: WellKnownSymbol) -> Result<Value, Exception> {
+        let ctx = Context(unsafe { self.as_ptr() });
+        let global = Object(ctx, unsafe { JSContextGetGlobalObject(ctx.0) });
+        let constructor = Object::try_from(&global.get("Symbol")?)
+            .map_err(|_| Exception::make(ctx, "TypeError", "Symbol is not an object"))?;
+        let value = constructor.get(symbol.name())?;
+        if value.js_type() != ValueType::Symbol {
+            let message = format!("Symbol.{} is not a symbol", symbol.name());
+            return Err(Exception::make(ctx, "TypeError", &message));
+        }
+        Ok(value)
+    }
+
+    #[cfg(feature = "bigint")]
+    fn bigint_from_i64(&self, n: i64) -> Result<Value, Exception> {
+        let ctx = Context(unsafe { self.as_ptr() });
+        let mut exception = null();
+        let value = unsafe { JSBigIntCreateWithInt64(ctx.0, n, &mut exception) };
+        ctx.result(value, exception)
+    }
+
+    #[cfg(feature = "bigint")]
+    fn bigint_from_u64(&self, n: u64) -> Result<Value, Exception> {
+        let ctx = Context(unsafe { self.as_ptr() });
+        let mut exception = null();
+        let value = unsafe { JSBigIntCreateWithUInt64(ctx.0, n, &mut exception) };
+        ctx.result(value, exception)
+    }
+
+    #[cfg(feature = "bigint")]
+    fn bigint_from_i128(&self, n: i128) -> Result<Value, Exception> {
+        self.bigint_from_str(&n.to_string())
+    }
+
+    /// Parses a BigInt from a string, as `BigInt(digits)` does.
+    #[cfg(feature = "bigint")]
+    fn bigint_from_str(&self, digits: &str) -> Result<Value, Exception> {
+        let ctx = Context(unsafe { self.as_ptr() });
+        let digits = String::from(digits);
+        let mut exception = null();
+        let value = unsafe { JSBigIntCreateWithString(ctx.0, *digits, &mut exception) };
+        ctx.result(value, exception)
+    }
+
+    fn parse_json(&self, json: &str) -> Result<Value, Exception> {
+        let ctx = Context(unsafe { self.as_ptr() });
+        let json = String::from(json);
+        let value = unsafe { JSValueMakeFromJSONString(ctx.0, *json) };
+        if !value.is_null() {
+            return Ok(Value::from(ctx, value));
+        }
+
+        // JavaScriptCore doesn't report why parsing failed, so let `JSON.parse`
+        // throw the `SyntaxError` for us. Scripts may have replaced or removed
+        // it, in which case a plain `SyntaxError` has to do.
+        let global = Object(ctx, unsafe { JSContextGetGlobalObject(ctx.0) });
+        let json_obj = global
+            .get("JSON")
+            .ok()
+            .and_then(|v| Object::try_from(&v).ok());
+        let parse = json_obj
+            .as_ref()
+            .and_then(|json_obj| json_obj.get("parse").ok())
+            .and_then(|v| Object::try_from(&v).ok())
+            .filter(Object::is_function);
+        if let (Some(json_obj), Some(parse)) = (json_obj, parse) {
+            if let Err(e) = parse.call_raw(json_obj.1, &[json.to_js_value(&ctx)]) {
+                if e.name().as_ref().map(std::string::String::as_str) == Some("SyntaxError") {
+                    return Err(e);
+                }
+            }
+        }
+        Err(Exception::make(ctx, "SyntaxError", "invalid JSON"))
+    }
+
+    /// Creates an array holding `elements`, as `[a, b, c]` does.
+    fn make_array(&self, elements: &[Value]) -> Result<Array, Exception> {
+        let ctx = Context(unsafe { self.as_ptr() });
+        let elements = elements.iter().map(|e| e.0).collect::<Vec<_>>();
+        let mut exception = null();
+        let ptr =
+            unsafe { JSObjectMakeArray(ctx.0, elements.len(), elements.as_ptr(), &mut exception) };
+        ctx.check(exception)?;
+        Ok(Array(Object(ctx, ptr)))
+    }
+
+    /// Checks that `source` parses as a script without running it.
+    /// `starting_line` is the line number of its first line, counting from 1.
+    fn check_syntax(
+        &self,
+        source: &str,
+        source_url: Option<&str>,
+        starting_line: i32,
+    ) -> Result<(), SyntaxError> {
+        let ctx = Context(unsafe { self.as_ptr() });
+        let source = String::from(source);
+        let source_url = source_url.map(String::from);
+        let source_url = source_url.as_ref().map_or(null_mut(), |url| url.0);
+        let mut exception = null();
+        let valid = unsafe {
+            JSCheckScriptSyntax(ctx.0, *source, source_url, starting_line, &mut exception)
+        };
+        if valid {
+            return Ok(());
+        }
+
+        let error = match exception.is_null() {
+            false => Object::try_from(&Value::from(ctx, exception)).ok(),
+            true => None,
+        };
+        let error = match error {
+            Some(error) => error,
+            None => {
+                return Err(SyntaxError {
+                    message: "invalid syntax".into(),
+                    line: None,
+                    column: None,
+                })
+            }
+        };
+        let position = |name: &str| {
+            let n = error.get(name).ok()?.to_number().ok()?;
+            if n.is_finite() && n >= 1.0 {
+                Some(n as u32)
+            } else {
+                None
+            }
+        };
+        let message = error
+            .get("message")
+            .ok()
+            .and_then(|message| std::string::String::try_from(&message).ok())
+            .unwrap_or_else(|| "invalid syntax".into());
+        Err(SyntaxError {
+            message,
+            line: position("line"),
+            column: position("column"),
+        })
+    }
+
+    /// Compiles `body` into a function taking the named parameters, as
+    /// `new Function(...params, body)` does, but with a name and a source
+    /// location for stack traces. Fails with a `SyntaxError` exception.
+    fn compile_function(
+        &self,
+        name: &str,
+        params: &[&str],
+        body: &str,
+        source_url: Option<&str>,
+        starting_line: i32,
+    ) -> Result<Object, Exception> {
+        let ctx = Context(unsafe { self.as_ptr() });
+        let name = String::from(name);
+        let params = params.iter().map(|&p| String::from(p)).collect::<Vec<_>>();
+        let param_refs = params.iter().map(|p| p.0).collect::<Vec<_>>();
+        let body = String::from(body);
+        let source_url = source_url.map(String::from);
+        let source_url = source_url.as_ref().map_or(null_mut(), |url| url.0);
+        let mut exception = null();
+        let function = unsafe {
+            JSObjectMakeFunction(
+                ctx.0,
+                *name,
+                param_refs.len() as _,
+                param_refs.as_ptr(),
+                *body,
+                source_url,
+                starting_line,
+                &mut exception,
+            )
+        };
+        ctx.check(exception)?;
+        Ok(Object(ctx, function))
+    }
+
+    /// Wraps `value` in an instance of the native class registered for `T`.
+    fn make_instance<T: Send + 'static>(&self, value: T) -> Result<Object, ClassError> {
+        let ptr = unsafe { self.as_ptr() };
+        class::make_instance(Context(ptr), value)
+    }
+
+    /// Creates the constructor for the native class registered for `T`, whose
+    /// `prototype` is shared by every instance of the class. JavaScript classes
+    /// may extend it, with `super()` calling the Rust constructor.
+    fn class_constructor<T: Send + 'static>(&self) -> Result<Object, ClassError> {
+        let ptr = unsafe { self.as_ptr() };
+        class::make_constructor::<T>(Context(ptr))
+    }
 }
 
 impl ContextType for GlobalContext {
@@ -130,6 +410,31 @@ impl ContextType for Context {
     }
 }
 
+impl Context {
+    pub(crate) fn check(self, exception: JSValueRef) -> Result<(), Exception> {
+        if exception.is_null() {
+            Ok(())
+        } else {
+            Err(Exception::new(Value::from(self, exception)))
+        }
+    }
+
+    pub(crate) fn result(
+        self,
+        value: JSValueRef,
+        exception: JSValueRef,
+    ) -> Result<Value, Exception> {
+        self.check(exception).map(|_| Value::from(self, value))
+    }
+
+    pub fn global_context(&self) -> GlobalContext {
+        let ptr = unsafe { JSGlobalContextRetain(JSContextGetGlobalContext(self.0)) };
+        let group = unsafe { ContextGroup::retain(JSContextGetGroup(self.0)) };
+        class::retain_intrinsics(ptr);
+        GlobalContext(ptr, group)
+    }
+}
+
 impl Deref for Context {
     type Target = JSContextRef;
 
@@ -146,52 +451,269 @@ impl Deref for Object {
     }
 }
 
-retain_release!(
-    ContextGroup,
-    JSContextGroupRef,
-    JSContextGroupRetain,
-    JSContextGroupRelease
-);
-retain_release!(
-    GlobalContext,
-    JSGlobalContextRef,
-    JSGlobalContextRetain,
-    JSGlobalContextRelease
-);
 retain_release!(String, JSStringRef, JSStringRetain, JSStringRelease);
+retain_release!(Class, JSClassRef, JSClassRetain, JSClassRelease);
+
+// Every `ContextGroup` handle also holds a reference to the group's class
+// registry, which releases its cached classes once the last handle drops.
+impl Drop for ContextGroup {
+    fn drop(&mut self) {
+        class::collect_garbage(self.0);
+        class::release_registry(self.0);
+        unsafe { JSContextGroupRelease(self.0) };
+        // Releasing the group may have finalized more objects.
+        class::collect_garbage(self.0);
+    }
+}
+
+impl Clone for ContextGroup {
+    fn clone(&self) -> ContextGroup {
+        unsafe { ContextGroup::retain(self.0) }
+    }
+}
+
+impl Deref for ContextGroup {
+    type Target = JSContextGroupRef;
+
+    fn deref(&self) -> &JSContextGroupRef {
+        &self.0
+    }
+}
+
+// Global contexts keep a handle on their group so the class registry
+// outlives every context that may use it.
+impl Drop for GlobalContext {
+    fn drop(&mut self) {
+        class::release_intrinsics(self.0);
+        unsafe { JSGlobalContextRelease(self.0) };
+    }
+}
+
+impl Clone for GlobalContext {
+    fn clone(&self) -> GlobalContext {
+        let ptr = unsafe { JSGlobalContextRetain(self.0) };
+        class::retain_intrinsics(ptr);
+        GlobalContext(ptr, self.1.clone())
+    }
+}
+
+impl Deref for GlobalContext {
+    type Target = JSGlobalContextRef;
+
+    fn deref(&self) -> &JSGlobalContextRef {
+        &self.0
+    }
+}
 
 impl ContextGroup {
     pub fn new() -> ContextGroup {
         let ptr = unsafe { JSContextGroupCreate() };
+        class::retain_registry(ptr);
+        ContextGroup(ptr)
+    }
+
+    pub(crate) unsafe fn retain(ptr: JSContextGroupRef) -> ContextGroup {
+        JSContextGroupRetain(ptr);
+        class::retain_registry(ptr);
         ContextGroup(ptr)
     }
 
     pub fn create_global_context(&self) -> GlobalContext {
         let ptr = unsafe { JSGlobalContextCreateInGroup(self.0, null_mut()) };
-        GlobalContext(ptr)
+        class::retain_intrinsics(ptr);
+        GlobalContext(ptr, self.clone())
+    }
+}
+
+// Calls a built-in such as `Object.defineProperty` as it was when the
+// context was created.
+pub(crate) fn call_intrinsic(
+    ctx: Context,
+    name: &str,
+    args: &[JSValueRef],
+) -> Result<Value, Exception> {
+    call_intrinsic_on(ctx, name, null_mut(), args)
+}
+
+// Like `call_intrinsic`, for built-ins such as `Map.prototype.forEach` that
+// need a `this`.
+pub(crate) fn call_intrinsic_on(
+    ctx: Context,
+    name: &str,
+    this: JSObjectRef,
+    args: &[JSValueRef],
+) -> Result<Value, Exception> {
+    let function = class::intrinsic(ctx, name);
+    if function.is_null() || !unsafe { JSValueIsObject(ctx.0, function) } {
+        let message = format!("{} is not available", name);
+        return Err(Exception::make(ctx, "TypeError", &message));
+    }
+    let function = Object(ctx, function as JSObjectRef);
+    if !function.is_function() {
+        let message = format!("{} is not a function", name);
+        return Err(Exception::make(ctx, "TypeError", &message));
+    }
+    function.call_raw(this, args)
+}
+
+impl Class {
+    pub(crate) fn new(definition: &JSClassDefinition) -> Class {
+        Class(unsafe { JSClassCreate(definition) })
     }
 }
 
-pub struct Exception(Object);
+/// A value thrown by JavaScript. Scripts may throw anything, not just
+/// errors, so every accessor is `None` when the value doesn't have it.
+#[derive(Clone)]
+pub struct Exception(Rooted<Value>);
 
 impl Exception {
-    pub fn stack(&self) -> std::string::String {
-        let stack_val = self
-            .0
-            .get_property(&String::new("stack").unwrap())
-            .expect("no `stack` property found");
-        let stack_str = String::try_from(&stack_val).expect("no string property found for `stack`");
-        std::string::String::from(&stack_str)
+    pub(crate) fn new(value: Value) -> Exception {
+        Exception(value.root())
+    }
+
+    /// Constructs an error using one of the global error constructors, such
+    /// as `TypeError`.
+    pub(crate) fn make(ctx: Context, constructor: &str, message: &str) -> Exception {
+        let message = String::from(message).to_js_value(&ctx);
+        let name = String::from(constructor);
+        let mut exception = null();
+        let error = unsafe {
+            let global = JSContextGetGlobalObject(ctx.0);
+            let constructor = JSObjectGetProperty(ctx.0, global, *name, &mut exception);
+            if exception.is_null() && !constructor.is_null() && JSValueIsObject(ctx.0, constructor)
+            {
+                let constructor = constructor as JSObjectRef;
+                JSObjectCallAsConstructor(ctx.0, constructor, 1, &message, &mut exception)
+            } else {
+                null_mut()
+            }
+        };
+        if !error.is_null() && exception.is_null() {
+            return Exception::new(Object(ctx, error).to_js_value());
+        }
+
+        // The constructor is missing or was replaced by something that throws,
+        // so fall back to a plain `Error` named after it.
+        let mut exception = null();
+        let error = unsafe { JSObjectMakeError(ctx.0, 1, &message, &mut exception) };
+        if error.is_null() || !exception.is_null() {
+            return Exception::new(Value::from(ctx, message));
+        }
+        let attributes = kJSPropertyAttributeDontEnum as JSPropertyAttributes;
+        unsafe {
+            let key = String::from("name");
+            let name = name.to_js_value(&ctx);
+            JSObjectSetProperty(ctx.0, error, *key, name, attributes, null_mut());
+        }
+        Exception::new(Object(ctx, error).to_js_value())
+    }
+
+    /// The value exactly as it was thrown, e.g. `42` for `throw 42`.
+    pub fn thrown_value(&self) -> Value {
+        self.0.local()
+    }
+
+    /// Whether the thrown value is an instance of `Error`.
+    pub fn is_error(&self) -> bool {
+        let value = self.thrown_value();
+        let ctx = value.2;
+        let global = Object(ctx, unsafe { JSContextGetGlobalObject(*ctx) });
+        match global.get("Error").map(|error| Object::try_from(&error)) {
+            Ok(Ok(error)) => unsafe {
+                JSValueIsInstanceOfConstructor(*ctx, value.0, error.1, null_mut())
+            },
+            _ => false,
+        }
+    }
+
+    pub fn name(&self) -> Option<std::string::String> {
+        self.string_property("name")
+    }
+
+    pub fn message(&self) -> Option<std::string::String> {
+        self.string_property("message")
+    }
+
+    pub fn stack(&self) -> Option<std::string::String> {
+        self.string_property("stack")
+    }
+
+    /// The parsed `stack`, innermost frame first, or empty if the thrown
+    /// value has no stack.
+    pub fn stack_frames(&self) -> Vec<StackFrame> {
+        self.stack()
+            .map_or_else(Vec::new, |stack| stack::parse_stack(&stack))
+    }
+
+    pub fn line(&self) -> Option<u32> {
+        self.number_property("line")
+    }
+
+    pub fn column(&self) -> Option<u32> {
+        self.number_property("column")
+    }
+
+    pub fn source_url(&self) -> Option<std::string::String> {
+        self.string_property("sourceURL")
     }
 
-    pub fn message(&self) -> std::string::String {
-        let message_val = self
-            .0
-            .get_property(&String::new("message").unwrap())
-            .expect("no `message` property found");
-        let message_str =
-            String::try_from(&message_val).expect("no string property found for `message`");
-        std::string::String::from(&message_str)
+    fn property(&self, name: &str) -> Option<Value> {
+        Object::try_from(&self.thrown_value()).ok()?.get(name).ok()
+    }
+
+    fn string_property(&self, name: &str) -> Option<std::string::String> {
+        std::string::String::try_from(&self.property(name)?).ok()
+    }
+
+    fn number_property(&self, name: &str) -> Option<u32> {
+        let n = f64::try_from(&self.property(name)?).ok()?;
+        if n.is_finite() && n >= 0.0 && n.fract() == 0.0 {
+            Some(n as u32)
+        } else {
+            None
+        }
+    }
+}
+
+/// A script that failed to parse, as reported by `ContextType::check_syntax`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub message: std::string::String,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+impl std::error::Error for SyntaxError {}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{}:{}: {}", line, column, self.message),
+            (Some(line), None) => write!(f, "{}: {}", line, self.message),
+            _ => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Where a script comes from, for stack traces and error locations, and the
+/// `this` it runs with.
+#[derive(Debug, Clone)]
+pub struct EvaluateOptions {
+    pub source_url: Option<std::string::String>,
+    /// The line number of the script's first line, counting from 1.
+    pub starting_line: i32,
+    /// `this` for the script's top-level code; the global object if `None`.
+    pub this: Option<Object>,
+}
+
+impl Default for EvaluateOptions {
+    fn default() -> EvaluateOptions {
+        EvaluateOptions {
+            source_url: None,
+            starting_line: 1,
+            this: None,
+        }
     }
 }
 
@@ -202,38 +724,121 @@ impl GlobalContext {
     }
 
     pub fn evaluate_script_sync(&self, script: &String) -> Result<Value, Exception> {
+        self.evaluate_script_sync_with_options(script, &EvaluateOptions::default())
+    }
+
+    pub fn evaluate_script_sync_with_options(
+        &self,
+        script: &String,
+        options: &EvaluateOptions,
+    ) -> Result<Value, Exception> {
+        class::collect_garbage(unsafe { JSContextGetGroup(self.0) });
+        let source_url = options
+            .source_url
+            .as_ref()
+            .map(|url| String::from(url.as_str()));
+        let source_url = source_url.as_ref().map_or(null_mut(), |url| url.0);
+        let this = options.this.as_ref().map_or(null_mut(), |this| this.1);
         let mut exception = null();
         let ret = unsafe {
-            JSEvaluateScript(self.0, **script, null_mut(), null_mut(), 0, &mut exception)
+            JSEvaluateScript(
+                self.0,
+                **script,
+                this,
+                source_url,
+                options.starting_line,
+                &mut exception,
+            )
         };
-        if exception == null_mut() {
-            Ok(Value::from(Context(self.0), ret))
-        } else {
-            let value = Value::from(Context(self.0), exception);
-            let obj = Object::try_from(&value).unwrap();
-            Err(Exception(obj))
-        }
+        Context(self.0).result(ret, exception)
     }
 
     pub async fn evaluate_script<'a>(&'a self, script: &'a String) -> Result<Value, Exception> {
         self.evaluate_script_sync(script)
     }
 
-    pub fn add_function(
-        &self,
-        name: &str,
-        callback: JsCallback,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let name = String::new(name).unwrap();
+    pub async fn evaluate_script_with_options<'a>(
+        &'a self,
+        script: &'a String,
+        options: &'a EvaluateOptions,
+    ) -> Result<Value, Exception> {
+        self.evaluate_script_sync_with_options(script, options)
+    }
+
+    /// Exposes the native class registered for `T` as a global under its
+    /// class name.
+    pub fn add_class<T: Send + 'static>(&self) -> Result<(), ClassError> {
+        let ctx = Context(self.0);
+        let name = class::class_name::<T>(ctx).ok_or(ClassError::NotRegistered)?;
+        let constructor = self.class_constructor::<T>()?;
+        let name = String::new(&name).map_err(|_| ClassError::InvalidName(name))?;
+        self.global_object()
+            .set_property(&name, constructor.to_js_value())
+            .map_err(ClassError::Exception)
+    }
+
+    pub fn add_function<F>(&self, name: &str, callback: F) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: Fn(Context, Object, Vec<Value>) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        let name = String::new(name)?;
         let obj = self.global_object();
         let fn_obj = obj.make_function_with_callback(&name, callback);
-        obj.set_property(&name, Value::from(Context(self.0), *fn_obj));
+        obj.set_property(&name, fn_obj.to_js_value())?;
         Ok(())
     }
+
+    /// Like `add_function`, but for closures that need mutable access to their
+    /// captured state. Re-entrant calls throw rather than alias that state.
+    pub fn add_function_mut<F>(
+        &self,
+        name: &str,
+        callback: F,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        F: FnMut(Context, Object, Vec<Value>) -> Result<Value, String> + Send + 'static,
+    {
+        let callback = Mutex::new(callback);
+        self.add_function(name, move |ctx, this, args| match callback.try_lock() {
+            Ok(mut callback) => (&mut *callback)(ctx, this, args),
+            Err(TryLockError::WouldBlock) => Err(String::from("host function called re-entrantly")),
+            Err(TryLockError::Poisoned(_)) => Err(String::from("host function panicked")),
+        })
+    }
 }
 
-type JsCallback =
-    fn(Context, /*thisObject*/ Object, /*arguments*/ Vec<Value>) -> Result<Value, String>;
+// Contexts can be used from any thread, so host functions must be callable
+// and droppable from any thread too.
+pub(crate) type JsCallback = Box<
+    dyn Fn(
+            Context,
+            /*thisObject*/ Object,
+            /*arguments*/ Vec<Value>,
+        ) -> Result<Value, String>
+        + Send
+        + Sync,
+>;
+
+// The private data of a host function. The group is kept so the finalizer
+// can queue the callback to be dropped later, and the owner marks methods
+// installed by a native class.
+struct FunctionData {
+    group: usize,
+    owner: Option<TypeId>,
+    callback: JsCallback,
+}
+
+/// The native class that installed `value` as one of its methods, if any.
+pub(crate) fn function_owner(ctx: Context, value: JSValueRef) -> Option<TypeId> {
+    let cls = class::function_class(ctx);
+    unsafe {
+        if value.is_null() || !JSValueIsObjectOfClass(ctx.0, value, *cls) {
+            return None;
+        }
+        let data = JSObjectGetPrivate(value as JSObjectRef) as *const FunctionData;
+        data.as_ref().and_then(|data| data.owner)
+    }
+}
 
 extern "C" fn callback_trampoline(
     ctx: JSContextRef,
@@ -243,9 +848,7 @@ extern "C" fn callback_trampoline(
     arguments: *const JSValueRef,
     exception: *mut JSValueRef,
 ) -> JSValueRef {
-    let callback = unsafe {
-        std::mem::transmute::<*mut ::std::os::raw::c_void, JsCallback>(JSObjectGetPrivate(function))
-    };
+    let data = unsafe { &*(JSObjectGetPrivate(function) as *const FunctionData) };
 
     let ctx = Context(ctx);
 
@@ -256,7 +859,7 @@ extern "C" fn callback_trampoline(
             .collect::<Vec<_>>()
     };
 
-    match callback(ctx, Object(ctx, this_object), args) {
+    match class::guard(|| (data.callback)(ctx, Object(ctx, this_object), args)) {
         Ok(v) => v.0,
         Err(e) => unsafe {
             *exception = e.to_js_value(&ctx);
@@ -265,11 +868,21 @@ extern "C" fn callback_trampoline(
     }
 }
 
+extern "C" fn callback_finalize(function: JSObjectRef) {
+    let data = unsafe { JSObjectGetPrivate(function) as *mut FunctionData };
+    if !data.is_null() {
+        let data = unsafe { Box::from_raw(data) };
+        class::defer(data.group, class::Garbage::new(move || drop(data)));
+    }
+}
+
 impl ValueType {
     unsafe fn from(ctx: Context, value_ref: JSValueRef) -> ValueType {
-        let raw_ty = JSValueGetType(ctx.0, value_ref);
+        ValueType::from_raw(JSValueGetType(ctx.0, value_ref)).unwrap_or(ValueType::Unknown)
+    }
 
-        match raw_ty {
+    pub(crate) fn from_raw(raw_ty: JSType) -> Option<ValueType> {
+        Some(match raw_ty {
             0 => ValueType::Undefined,
             1 => ValueType::Null,
             2 => ValueType::Boolean,
@@ -277,51 +890,28 @@ impl ValueType {
             4 => ValueType::String,
             5 => ValueType::Object,
             6 => ValueType::Symbol,
-            _ => unreachable!(),
-        }
+            7 => ValueType::BigInt,
+            _ => return None,
+        })
     }
 }
 
 impl Value {
-    fn from(ctx: Context, value_ref: JSValueRef) -> Value {
+    pub(crate) fn from(ctx: Context, value_ref: JSValueRef) -> Value {
         Value(value_ref, unsafe { ValueType::from(ctx, value_ref) }, ctx)
     }
-
-    pub fn to_string(&self) -> std::string::String {
-        match self.js_type() {
-            ValueType::String => {
-                let js_str = String::try_from(self).expect("string");
-                std::string::String::from(&js_str)
-            }
-            ValueType::Number => {
-                let n = f64::try_from(self).expect("f64");
-                format!("{}", n)
-            }
-            ValueType::Boolean => {
-                let v = bool::try_from(self).expect("bool");
-                format!("{}", v)
-            }
-            ValueType::Null => "null".into(),
-            ValueType::Undefined => "undefined".into(),
-            ValueType::Symbol => "Symbol(...)".into(),
-            ValueType::Object => {
-                let obj = Object::try_from(self).expect("object");
-                format!("{:?}", obj)
-            }
-        }
-    }
 }
 
-fn rust_function_defn(name: &String) -> JSClassDefinition {
+pub(crate) fn rust_function_defn() -> JSClassDefinition {
     JSClassDefinition {
         version: 0,
         attributes: 0,
-        className: **name as *const _,
+        className: b"Function\0".as_ptr() as *const _,
         parentClass: null_mut(),
         staticValues: null(),
         staticFunctions: null(),
         initialize: None,
-        finalize: None,
+        finalize: Some(callback_finalize),
         hasProperty: None,
         getProperty: None,
         setProperty: None,
@@ -338,35 +928,881 @@ impl Value {
     pub fn js_type(&self) -> ValueType {
         self.1
     }
+
+    /// ECMAScript `ToNumber`, which may call `valueOf` and so may throw.
+    pub fn to_number(&self) -> Result<f64, Exception> {
+        let mut exception = null();
+        let n = unsafe { JSValueToNumber(*self.2, self.0, &mut exception) };
+        if exception.is_null() {
+            Ok(n)
+        } else {
+            Err(Exception::new(Value::from(self.2, exception)))
+        }
+    }
+
+    /// ECMAScript `ToInt32`, as used by bitwise operators: the number wraps
+    /// around rather than being range checked.
+    pub fn to_int32(&self) -> Result<i32, Exception> {
+        Ok(convert::to_uint32(self.to_number()?) as i32)
+    }
+
+    /// ECMAScript `ToUint32`, as used by `>>>`: the number wraps around
+    /// rather than being range checked.
+    pub fn to_uint32(&self) -> Result<u32, Exception> {
+        Ok(convert::to_uint32(self.to_number()?))
+    }
+
+    /// The description of a symbol, or `None` if the value is not a symbol or
+    /// was created without one.
+    pub fn description(&self) -> Option<std::string::String> {
+        match self.js_type() {
+            ValueType::Symbol => {}
+            _ => return None,
+        }
+        let description = unsafe {
+            let wrapper = JSValueToObject(*self.2, self.0, null_mut());
+            let name = String::from("description");
+            Value::from(
+                self.2,
+                JSObjectGetProperty(*self.2, wrapper, *name, null_mut()),
+            )
+        };
+        std::string::String::try_from(&description).ok()
+    }
+
+    /// Converts the value into a Rust value, e.g. `let v: Vec<String> = value.get()?`.
+    pub fn get<T: FromValue>(&self) -> Result<T, TryFromValueError> {
+        T::from_value(self)
+    }
+
+    /// Serializes the value as `JSON.stringify` would, indenting nested
+    /// levels by `indent` spaces (at most 10), or not at all if `indent` is 0.
+    pub fn to_json(&self, indent: u32) -> Result<std::string::String, Exception> {
+        let mut exception = null();
+        let json = unsafe { JSValueCreateJSONString(*self.2, self.0, indent, &mut exception) };
+        if !exception.is_null() {
+            return Err(Exception::new(Value::from(self.2, exception)));
+        }
+        if json.is_null() {
+            let message = "value cannot be represented as JSON";
+            return Err(Exception::make(self.2, "TypeError", message));
+        }
+        Ok(std::string::String::from(&String(json)))
+    }
 }
 
 impl Object {
-    pub fn make_function_with_callback(&self, name: &String, callback: JsCallback) -> Object {
-        let cls = unsafe { JSClassCreate(&rust_function_defn(name)) };
-        let ptr = unsafe { JSObjectMake(*self.0, cls, callback as _) };
+    pub fn make_function_with_callback<F>(&self, name: &String, callback: F) -> Object
+    where
+        F: Fn(Context, Object, Vec<Value>) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.make_host_function(name, None, Box::new(callback))
+    }
+
+    pub(crate) fn make_method<F>(&self, name: &String, owner: TypeId, callback: F) -> Object
+    where
+        F: Fn(Context, Object, Vec<Value>) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.make_host_function(name, Some(owner), Box::new(callback))
+    }
+
+    fn make_host_function(
+        &self,
+        name: &String,
+        owner: Option<TypeId>,
+        callback: JsCallback,
+    ) -> Object {
+        let data = Box::new(FunctionData {
+            group: unsafe { JSContextGetGroup(*self.0) } as usize,
+            owner,
+            callback,
+        });
+        let cls = class::function_class(self.0);
+        let ptr = unsafe { JSObjectMake(*self.0, *cls, Box::into_raw(data) as _) };
         if unsafe { JSObjectGetPrivate(ptr) } == null_mut() {
             panic!("No private");
         }
+        let name_value = name.to_js_value(&self.0);
+        let attributes = kJSPropertyAttributeReadOnly | kJSPropertyAttributeDontEnum;
+        unsafe {
+            JSObjectSetProperty(
+                *self.0,
+                ptr,
+                *String::new("name").unwrap(),
+                name_value,
+                attributes as _,
+                null_mut(),
+            )
+        };
         Object(self.0, ptr)
     }
 
-    pub fn set_property(&self, name: &String, value: Value) {
-        unsafe { JSObjectSetProperty(*self.0, self.1, **name, value.0, 0, null_mut()) };
+    pub fn set_property(&self, name: &String, value: Value) -> Result<(), Exception> {
+        self.set(name, value)
+    }
+
+    pub fn get_property(&self, name: &String) -> Result<Value, Exception> {
+        self.get(name)
+    }
+
+    pub fn has_property(&self, name: &String) -> Result<bool, Exception> {
+        self.has(name)
+    }
+
+    pub fn delete_property(&self, name: &String) -> Result<bool, Exception> {
+        self.delete(name)
+    }
+
+    pub fn get_property_at_index(&self, index: u32) -> Result<Value, Exception> {
+        self.get(index)
+    }
+
+    pub fn set_property_at_index(&self, index: u32, value: Value) -> Result<(), Exception> {
+        self.set(index, value)
     }
 
-    pub fn get_property(&self, name: &String) -> Result<Value, Value> {
+    /// The object's prototype, as `Object.getPrototypeOf` returns it. Unlike
+    /// `JSObjectGetPrototype`, this surfaces exceptions thrown by proxies.
+    pub fn prototype(&self) -> Result<Value, Exception> {
+        call_intrinsic(self.0, "Object.getPrototypeOf", &[self.1 as JSValueRef])
+    }
+
+    /// Sets the object's prototype to an object or `null`, as
+    /// `Object.setPrototypeOf` does.
+    pub fn set_prototype(&self, prototype: &Value) -> Result<(), Exception> {
+        let args = [self.1 as JSValueRef, prototype.0];
+        call_intrinsic(self.0, "Object.setPrototypeOf", &args).map(|_| ())
+    }
+
+    /// Gets a property, e.g. `obj.get("name")` or `obj.get(0)`.
+    pub fn get<K: Into<PropertyKey>>(&self, key: K) -> Result<Value, Exception> {
         let mut exception = null();
-        let ret = unsafe { JSObjectGetProperty(*self.0, self.1, **name, &mut exception) };
-        if exception == null() {
-            Ok(Value::from(self.0, ret))
-        } else {
-            Err(Value::from(self.0, exception))
+        let ret = unsafe {
+            match key.into() {
+                PropertyKey::String(name) => {
+                    JSObjectGetProperty(*self.0, self.1, *name, &mut exception)
+                }
+                PropertyKey::Index(index) => {
+                    JSObjectGetPropertyAtIndex(*self.0, self.1, index, &mut exception)
+                }
+                PropertyKey::Symbol(key) | PropertyKey::Value(key) => {
+                    JSObjectGetPropertyForKey(*self.0, self.1, key.0, &mut exception)
+                }
+            }
+        };
+        self.0.result(ret, exception)
+    }
+
+    pub fn set<K: Into<PropertyKey>>(&self, key: K, value: Value) -> Result<(), Exception> {
+        let mut exception = null();
+        unsafe {
+            match key.into() {
+                PropertyKey::String(name) => {
+                    JSObjectSetProperty(*self.0, self.1, *name, value.0, 0, &mut exception)
+                }
+                PropertyKey::Index(index) => {
+                    JSObjectSetPropertyAtIndex(*self.0, self.1, index, value.0, &mut exception)
+                }
+                PropertyKey::Symbol(key) | PropertyKey::Value(key) => {
+                    JSObjectSetPropertyForKey(*self.0, self.1, key.0, value.0, 0, &mut exception)
+                }
+            }
+        };
+        self.0.check(exception)
+    }
+
+    /// Whether the object or its prototype chain has the property, as the
+    /// `in` operator does.
+    pub fn has<K: Into<PropertyKey>>(&self, key: K) -> Result<bool, Exception> {
+        let mut exception = null();
+        // `JSObjectHasProperty` would swallow anything a proxy's `has` trap
+        // throws, so every key goes through `JSObjectHasPropertyForKey`.
+        let key = key.into().to_js_value(self.0);
+        let has = unsafe { JSObjectHasPropertyForKey(*self.0, self.1, key, &mut exception) };
+        self.0.check(exception).map(|_| has)
+    }
+
+    /// Deletes a property, returning whether it was deleted; non-configurable
+    /// properties are not.
+    pub fn delete<K: Into<PropertyKey>>(&self, key: K) -> Result<bool, Exception> {
+        let mut exception = null();
+        let deleted = unsafe {
+            match key.into() {
+                PropertyKey::String(name) => {
+                    JSObjectDeleteProperty(*self.0, self.1, *name, &mut exception)
+                }
+                key => {
+                    let key = key.to_js_value(self.0);
+                    JSObjectDeletePropertyForKey(*self.0, self.1, key, &mut exception)
+                }
+            }
+        };
+        self.0.check(exception).map(|_| deleted)
+    }
+
+    /// Defines a data property with the given attributes, as
+    /// `Object.defineProperty` does, replacing any existing configurable
+    /// property.
+    pub fn define_property<K: Into<PropertyKey>>(
+        &self,
+        key: K,
+        value: Value,
+        attributes: PropertyAttributes,
+    ) -> Result<(), Exception> {
+        let descriptor = attributes.descriptor(self.0)?;
+        descriptor.set("value", value)?;
+        let writable = unsafe { JSValueMakeBoolean(*self.0, !attributes.read_only) };
+        descriptor.set("writable", Value::from(self.0, writable))?;
+        self.define(key.into(), descriptor)
+    }
+
+    /// Defines a property whose value is computed by `getter` each time it is
+    /// read. Assigning to it is ignored, or throws in strict mode.
+    pub fn define_getter<K, G>(
+        &self,
+        key: K,
+        getter: G,
+        attributes: PropertyAttributes,
+    ) -> Result<(), Exception>
+    where
+        K: Into<PropertyKey>,
+        G: Fn(Context, Object) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        let key = key.into();
+        let descriptor = attributes.descriptor(self.0)?;
+        descriptor.set("get", self.accessor_function("get", &key, getter))?;
+        self.define(key, descriptor)
+    }
+
+    /// Defines a property backed by a Rust getter and setter. `read_only` has
+    /// no effect on accessor properties.
+    pub fn define_accessor<K, G, S>(
+        &self,
+        key: K,
+        getter: G,
+        setter: S,
+        attributes: PropertyAttributes,
+    ) -> Result<(), Exception>
+    where
+        K: Into<PropertyKey>,
+        G: Fn(Context, Object) -> Result<Value, String> + Send + Sync + 'static,
+        S: Fn(Context, Object, Value) -> Result<(), String> + Send + Sync + 'static,
+    {
+        let key = key.into();
+        let descriptor = attributes.descriptor(self.0)?;
+        descriptor.set("get", self.accessor_function("get", &key, getter))?;
+        let setter = move |ctx: Context, this: Object, args: Vec<Value>| {
+            let value = args.into_iter().next().unwrap_or_else(|| ctx.undefined());
+            setter(ctx, this, value).map(|_| ctx.undefined())
+        };
+        let setter = self.make_function_with_callback(&accessor_name("set", &key), setter);
+        descriptor.set("set", setter.to_js_value())?;
+        self.define(key, descriptor)
+    }
+
+    fn accessor_function<G>(&self, kind: &str, key: &PropertyKey, getter: G) -> Value
+    where
+        G: Fn(Context, Object) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        let getter = move |ctx: Context, this: Object, _: Vec<Value>| getter(ctx, this);
+        let name = accessor_name(kind, key);
+        self.make_function_with_callback(&name, getter)
+            .to_js_value()
+    }
+
+    fn define(&self, key: PropertyKey, descriptor: Object) -> Result<(), Exception> {
+        let args = [
+            self.1 as JSValueRef,
+            key.to_js_value(self.0),
+            descriptor.1 as JSValueRef,
+        ];
+        call_intrinsic(self.0, "Object.defineProperty", &args).map(|_| ())
+    }
+
+    pub fn is_function(&self) -> bool {
+        unsafe { JSObjectIsFunction(*self.0, self.1) }
+    }
+
+    pub fn is_constructor(&self) -> bool {
+        unsafe { JSObjectIsConstructor(*self.0, self.1) }
+    }
+
+    /// Calls the object as a function. JavaScriptCore has no way to pass an
+    /// undefined `this`, so `None` makes it the global object, even in strict
+    /// mode functions.
+    pub fn call(&self, this: Option<&Object>, args: &[Value]) -> Result<Value, Exception> {
+        if !self.is_function() {
+            return Err(Exception::make(
+                self.0,
+                "TypeError",
+                "object is not a function",
+            ));
+        }
+        let this = this.map_or(null_mut(), |this| this.1);
+        let args = args.iter().map(|arg| arg.0).collect::<Vec<_>>();
+        self.call_raw(this, &args)
+    }
+
+    /// Calls the object as a constructor, as `new f(...args)` does.
+    pub fn construct(&self, args: &[Value]) -> Result<Object, Exception> {
+        if !self.is_constructor() {
+            return Err(Exception::make(
+                self.0,
+                "TypeError",
+                "object is not a constructor",
+            ));
+        }
+        let args = args.iter().map(|arg| arg.0).collect::<Vec<_>>();
+        let mut exception = null();
+        let ret = unsafe {
+            JSObjectCallAsConstructor(*self.0, self.1, args.len(), args.as_ptr(), &mut exception)
+        };
+        self.0.check(exception)?;
+        Ok(Object(self.0, ret))
+    }
+
+    /// Calls the method `name` with this object as `this`, as
+    /// `object[name](...args)` does.
+    pub fn call_method<K: Into<PropertyKey>>(
+        &self,
+        name: K,
+        args: &[Value],
+    ) -> Result<Value, Exception> {
+        let key = name.into();
+        let method = self.get(key.clone())?;
+        match Object::try_from(&method) {
+            Ok(ref method) if method.is_function() => method.call(Some(self), args),
+            _ => {
+                let message = match key {
+                    PropertyKey::String(name) => {
+                        format!("{} is not a function", std::string::String::from(&name))
+                    }
+                    _ => "method is not a function".into(),
+                };
+                Err(Exception::make(self.0, "TypeError", &message))
+            }
+        }
+    }
+
+    fn call_raw(&self, this: JSObjectRef, args: &[JSValueRef]) -> Result<Value, Exception> {
+        let mut exception = null();
+        let ret = unsafe {
+            JSObjectCallAsFunction(
+                *self.0,
+                self.1,
+                this,
+                args.len(),
+                args.as_ptr(),
+                &mut exception,
+            )
+        };
+        self.0.result(ret, exception)
+    }
+
+    /// The object's enumerable string keys, including inherited ones, in the
+    /// order `for...in` visits them. Use `own_enumerable_keys` for the keys
+    /// `Object.keys` returns.
+    pub fn for_in_keys(&self) -> Keys {
+        PropertyNameArray::new(self).into_iter()
+    }
+
+    /// The values of the properties `for_in_keys` names.
+    pub fn for_in_values(&self) -> Values {
+        Values {
+            object: self.clone(),
+            keys: self.for_in_keys(),
+        }
+    }
+
+    /// The properties `for_in_keys` names, with their values.
+    pub fn for_in_entries(&self) -> Entries {
+        Entries {
+            object: self.clone(),
+            keys: self.for_in_keys(),
         }
     }
 
+    /// The object's own enumerable string keys, as `Object.keys` returns them.
+    pub fn own_enumerable_keys(&self) -> Result<Vec<String>, Exception> {
+        let keys = call_intrinsic(self.0, "Object.keys", &[self.1 as JSValueRef])?;
+        let keys = Array::try_from(&keys).map_err(|_| {
+            Exception::make(self.0, "TypeError", "Object.keys did not return an array")
+        })?;
+        (0..keys.len()?)
+            .map(|index| {
+                let key = keys.get(index)?;
+                String::try_from(&key).map_err(|_| {
+                    Exception::make(self.0, "TypeError", "Object.keys returned a non-string key")
+                })
+            })
+            .collect()
+    }
+
+    /// All of the object's own keys, including non-enumerable and symbol
+    /// keys, as `Reflect.ownKeys` returns them.
+    pub fn own_keys(&self) -> Result<Vec<PropertyKey>, Exception> {
+        let keys = call_intrinsic(self.0, "Reflect.ownKeys", &[self.1 as JSValueRef])?;
+        let keys = Array::try_from(&keys).map_err(|_| {
+            Exception::make(
+                self.0,
+                "TypeError",
+                "Reflect.ownKeys did not return an array",
+            )
+        })?;
+        keys.iter()
+            .map(|key| {
+                let key = key?;
+                Ok(match String::try_from(&key) {
+                    Ok(name) => PropertyKey::String(name),
+                    Err(_) => PropertyKey::from(key),
+                })
+            })
+            .collect()
+    }
+
     pub fn to_js_value(&self) -> Value {
         Value(self.1, ValueType::Object, self.0)
     }
+
+    /// Whether this object is an instance of the native class for `T`.
+    pub fn is<T: 'static>(&self) -> bool {
+        class::instance_data::<T>(self).is_some()
+    }
+
+    /// Borrows the Rust value of a native class instance. Returns `None` if
+    /// the object is not an instance of `T`'s class or the value is currently
+    /// borrowed mutably.
+    pub fn downcast_ref<T: 'static>(&self) -> Option<Ref<T>> {
+        class::instance_data::<T>(self)?.borrow::<T>()
+    }
+
+    /// Mutably borrows the Rust value of a native class instance. Returns
+    /// `None` if the object is not an instance of `T`'s class or the value is
+    /// currently borrowed.
+    pub fn downcast_mut<T: 'static>(&self) -> Option<RefMut<T>> {
+        class::instance_data::<T>(self)?.borrow_mut::<T>()
+    }
+
+    pub fn root(&self) -> Rooted<Object> {
+        Rooted::new(self.clone())
+    }
+}
+
+impl Value {
+    pub fn root(&self) -> Rooted<Value> {
+        Rooted::new(self.clone())
+    }
+
+    /// Whether the value is an array, as `Array.isArray` decides.
+    pub fn is_array(&self) -> bool {
+        unsafe { JSValueIsArray(*self.2, self.0) }
+    }
+}
+
+impl Array {
+    pub fn len(&self) -> Result<u32, Exception> {
+        let length = self.0.get("length")?.to_number()?;
+        Ok(convert::to_uint32(length))
+    }
+
+    pub fn is_empty(&self) -> Result<bool, Exception> {
+        Ok(self.len()? == 0)
+    }
+
+    pub fn get(&self, index: u32) -> Result<Value, Exception> {
+        self.0.get(index)
+    }
+
+    pub fn set(&self, index: u32, value: Value) -> Result<(), Exception> {
+        self.0.set(index, value)
+    }
+
+    /// Appends `value`, as `Array.prototype.push` does.
+    pub fn push(&self, value: Value) -> Result<(), Exception> {
+        let length = self.len()?;
+        self.0.set(length, value)
+    }
+
+    /// Iterates over the elements, up to the length the array had when
+    /// iteration started. If reading the length throws, that exception is
+    /// the only item.
+    pub fn iter(&self) -> ArrayIter {
+        let (len, error) = match self.len() {
+            Ok(len) => (len, None),
+            Err(e) => (0, Some(e)),
+        };
+        ArrayIter {
+            array: self.clone(),
+            index: 0,
+            len,
+            error,
+        }
+    }
+
+    pub fn to_vec(&self) -> Result<Vec<Value>, Exception> {
+        let len = self.len()?;
+        (0..len).map(|index| self.get(index)).collect()
+    }
+
+    pub fn as_object(&self) -> &Object {
+        &self.0
+    }
+
+    pub fn to_js_value(&self) -> Value {
+        self.0.to_js_value()
+    }
+}
+
+impl From<Array> for Object {
+    fn from(array: Array) -> Object {
+        array.0
+    }
+}
+
+impl<'a> IntoIterator for &'a Array {
+    type Item = Result<Value, Exception>;
+    type IntoIter = ArrayIter;
+
+    fn into_iter(self) -> ArrayIter {
+        self.iter()
+    }
+}
+
+pub struct ArrayIter {
+    array: Array,
+    index: u32,
+    len: u32,
+    error: Option<Exception>,
+}
+
+impl Iterator for ArrayIter {
+    type Item = Result<Value, Exception>;
+
+    fn next(&mut self) -> Option<Result<Value, Exception>> {
+        if let Some(error) = self.error.take() {
+            return Some(Err(error));
+        }
+        if self.index >= self.len {
+            return None;
+        }
+        let index = self.index;
+        self.index += 1;
+        Some(self.array.get(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.len - self.index) as usize + self.error.is_some() as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+/// A snapshot of an object's enumerable string keys, inherited ones
+/// included, as returned by `JSObjectCopyPropertyNames`. The names are
+/// released when it is dropped.
+pub struct PropertyNameArray(JSPropertyNameArrayRef);
+
+impl PropertyNameArray {
+    pub fn new(object: &Object) -> PropertyNameArray {
+        PropertyNameArray(unsafe { JSObjectCopyPropertyNames(*object.0, object.1) })
+    }
+
+    pub fn len(&self) -> usize {
+        unsafe { JSPropertyNameArrayGetCount(self.0) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<String> {
+        if index < self.len() {
+            Some(unsafe { String::retain(JSPropertyNameArrayGetNameAtIndex(self.0, index)) })
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for PropertyNameArray {
+    fn drop(&mut self) {
+        unsafe { JSPropertyNameArrayRelease(self.0) };
+    }
+}
+
+impl IntoIterator for PropertyNameArray {
+    type Item = String;
+    type IntoIter = Keys;
+
+    fn into_iter(self) -> Keys {
+        Keys {
+            names: self,
+            index: 0,
+        }
+    }
+}
+
+pub struct Keys {
+    names: PropertyNameArray,
+    index: usize,
+}
+
+impl Iterator for Keys {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let name = self.names.get(self.index)?;
+        self.index += 1;
+        Some(name)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.names.len() - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+/// The values of an object's enumerable properties, inherited ones included.
+/// Getters run as each value is read, so each item may be an exception.
+pub struct Values {
+    object: Object,
+    keys: Keys,
+}
+
+impl Iterator for Values {
+    type Item = Result<Value, Exception>;
+
+    fn next(&mut self) -> Option<Result<Value, Exception>> {
+        let key = self.keys.next()?;
+        Some(self.object.get(key))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+pub struct Entries {
+    object: Object,
+    keys: Keys,
+}
+
+impl Iterator for Entries {
+    type Item = Result<(String, Value), Exception>;
+
+    fn next(&mut self) -> Option<Result<(String, Value), Exception>> {
+        let key = self.keys.next()?;
+        Some(self.object.get(&key).map(|value| (key, value)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+/// Attributes of a property defined with `Object::define_property`. The
+/// default is a writable, enumerable and deletable property, like one created
+/// by assignment.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PropertyAttributes {
+    pub read_only: bool,
+    pub dont_enum: bool,
+    pub dont_delete: bool,
+}
+
+impl PropertyAttributes {
+    /// Read-only, non-enumerable and non-deletable, as suits host
+    /// configuration installed on the global object.
+    pub fn frozen() -> PropertyAttributes {
+        PropertyAttributes {
+            read_only: true,
+            dont_enum: true,
+            dont_delete: true,
+        }
+    }
+
+    // Descriptors have no prototype, so nothing a script adds to
+    // `Object.prototype` is read as a field.
+    fn descriptor(self, ctx: Context) -> Result<Object, Exception> {
+        let descriptor = Object(ctx, unsafe { JSObjectMake(*ctx, null_mut(), null_mut()) });
+        unsafe { JSObjectSetPrototype(*ctx, descriptor.1, JSValueMakeNull(*ctx)) };
+        let enumerable = unsafe { JSValueMakeBoolean(*ctx, !self.dont_enum) };
+        descriptor.set("enumerable", Value::from(ctx, enumerable))?;
+        let configurable = unsafe { JSValueMakeBoolean(*ctx, !self.dont_delete) };
+        descriptor.set("configurable", Value::from(ctx, configurable))?;
+        Ok(descriptor)
+    }
+}
+
+fn accessor_name(kind: &str, key: &PropertyKey) -> String {
+    match key {
+        PropertyKey::String(name) => {
+            let name = std::string::String::from(name);
+            String::from(&*format!("{} {}", kind, name))
+        }
+        PropertyKey::Index(index) => String::from(&*format!("{} {}", kind, index)),
+        PropertyKey::Symbol(_) | PropertyKey::Value(_) => String::from(kind),
+    }
+}
+
+/// The key of an object property. Indices and names avoid converting the key
+/// to a JavaScript value; any other value, such as a symbol, is converted to
+/// a property key as `object[key]` does.
+#[derive(Debug, Clone)]
+pub enum PropertyKey {
+    String(String),
+    Index(u32),
+    Symbol(Value),
+    Value(Value),
+}
+
+impl PropertyKey {
+    fn to_js_value(&self, ctx: Context) -> JSValueRef {
+        match self {
+            PropertyKey::String(name) => name.to_js_value(&ctx),
+            PropertyKey::Index(index) => unsafe { JSValueMakeNumber(*ctx, f64::from(*index)) },
+            PropertyKey::Symbol(key) | PropertyKey::Value(key) => key.0,
+        }
+    }
+}
+
+impl From<&str> for PropertyKey {
+    fn from(name: &str) -> PropertyKey {
+        PropertyKey::String(String::from(name))
+    }
+}
+
+impl From<&std::string::String> for PropertyKey {
+    fn from(name: &std::string::String) -> PropertyKey {
+        PropertyKey::String(String::from(name.as_str()))
+    }
+}
+
+impl From<String> for PropertyKey {
+    fn from(name: String) -> PropertyKey {
+        PropertyKey::String(name)
+    }
+}
+
+impl From<&String> for PropertyKey {
+    fn from(name: &String) -> PropertyKey {
+        PropertyKey::String(name.clone())
+    }
+}
+
+impl From<u32> for PropertyKey {
+    fn from(index: u32) -> PropertyKey {
+        PropertyKey::Index(index)
+    }
+}
+
+impl From<Value> for PropertyKey {
+    fn from(key: Value) -> PropertyKey {
+        match key.js_type() {
+            ValueType::Symbol => PropertyKey::Symbol(key),
+            _ => PropertyKey::Value(key),
+        }
+    }
+}
+
+impl From<&Value> for PropertyKey {
+    fn from(key: &Value) -> PropertyKey {
+        PropertyKey::from(key.clone())
+    }
+}
+
+/// A value or object that can be protected from garbage collection.
+pub trait Rootable: Clone {
+    fn context(&self) -> Context;
+    fn value_ref(&self) -> JSValueRef;
+    fn with_context(self, ctx: Context) -> Self;
+}
+
+impl Rootable for Value {
+    fn context(&self) -> Context {
+        self.2
+    }
+
+    fn value_ref(&self) -> JSValueRef {
+        self.0
+    }
+
+    fn with_context(self, ctx: Context) -> Value {
+        Value(self.0, self.1, ctx)
+    }
+}
+
+impl Rootable for Object {
+    fn context(&self) -> Context {
+        self.0
+    }
+
+    fn value_ref(&self) -> JSValueRef {
+        self.1
+    }
+
+    fn with_context(self, ctx: Context) -> Object {
+        Object(ctx, self.1)
+    }
+}
+
+/// A GC root for a `Value` or `Object`.
+///
+/// Plain `Value`s and `Object`s are unrooted locals: they are only safe to use
+/// while JavaScriptCore can see them, i.e. for the duration of a callback or
+/// script evaluation. A `Rooted` handle calls `JSValueProtect` when created or
+/// cloned and `JSValueUnprotect` when dropped, and keeps its global context
+/// alive, so it may be stored anywhere.
+pub struct Rooted<T: Rootable> {
+    value: T,
+    ctx: GlobalContext,
+}
+
+impl<T: Rootable> Rooted<T> {
+    pub fn new(value: T) -> Rooted<T> {
+        let ctx = value.context().global_context();
+        let value = value.with_context(Context(ctx.0));
+        unsafe { JSValueProtect(ctx.0, value.value_ref()) };
+        Rooted { value, ctx }
+    }
+
+    /// Returns an unrooted copy of the handle.
+    pub fn local(&self) -> T {
+        self.value.clone()
+    }
+
+    pub fn global_context(&self) -> &GlobalContext {
+        &self.ctx
+    }
+}
+
+impl<T: Rootable> Clone for Rooted<T> {
+    fn clone(&self) -> Rooted<T> {
+        unsafe { JSValueProtect(self.ctx.0, self.value.value_ref()) };
+        Rooted {
+            value: self.value.clone(),
+            ctx: self.ctx.clone(),
+        }
+    }
+}
+
+impl<T: Rootable> Drop for Rooted<T> {
+    fn drop(&mut self) {
+        unsafe { JSValueUnprotect(self.ctx.0, self.value.value_ref()) };
+    }
+}
+
+impl<T: Rootable> Deref for Rooted<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Rootable + fmt::Debug> fmt::Debug for Rooted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Rooted").field(&self.value).finish()
+    }
 }
 
 impl String {
@@ -376,7 +1812,20 @@ impl String {
         Ok(String(v))
     }
 
+    pub(crate) unsafe fn retain(ptr: JSStringRef) -> String {
+        String(JSStringRetain(ptr))
+    }
+
     pub fn to_js_value(&self, ctx: &Context) -> JSValueRef {
         unsafe { JSValueMakeString(**ctx, self.0) }
     }
 }
+
+// Unlike `String::new`, this copies UTF-16 code units and so also accepts
+// strings containing NUL.
+impl From<&str> for String {
+    fn from(s: &str) -> String {
+        let chars = s.encode_utf16().collect::<Vec<_>>();
+        String(unsafe { JSStringCreateWithCharacters(chars.as_ptr(), chars.len()) })
+    }
+}